@@ -1,3 +1,7 @@
+const OPTIONAL_STRING: &str = "#[serde(default, skip_serializing_if = \"String::is_empty\")]";
+const OPTIONAL_MESSAGE: &str = "#[serde(default, skip_serializing_if = \"Option::is_none\")]";
+const DEFAULT: &str = "#[serde(default)]";
+
 fn main() {
     // re build by changes [ build.rs, src/wire/wire.proto, Cargo.toml ]
     println!("cargo:rerun-if-changed=build.rs");
@@ -10,6 +14,25 @@ fn main() {
         .type_attribute(".", "#[serde(rename_all = \"snake_case\")]")
         // key is proto field
         .field_attribute("send_message", "#[serde(rename = \"send_message\")]")
+        // optional in json, old clients do not send them
+        .field_attribute("Login.password", OPTIONAL_STRING)
+        .field_attribute("Login.token", OPTIONAL_STRING)
+        .field_attribute("Ack.token", OPTIONAL_STRING)
+        .field_attribute("Ack.session_token", OPTIONAL_STRING)
+        .field_attribute("Ack.room_list", OPTIONAL_MESSAGE)
+        .field_attribute("Ack.member_list", OPTIONAL_MESSAGE)
+        // flags and counts clients may leave out, like protobuf does
+        .field_attribute("CreateRoom.persistent", DEFAULT)
+        .field_attribute("Typing.active", DEFAULT)
+        .field_attribute("Resume.last_seen", DEFAULT)
+        .field_attribute("FetchHistory.before_sequence", DEFAULT)
+        .field_attribute("FetchHistory.limit", DEFAULT)
+        .field_attribute("SubscribePresence.users", DEFAULT)
+        .field_attribute("SetPresence.status", DEFAULT)
+        // from the path in the rest api
+        .field_attribute("PublishRequest.topic", OPTIONAL_STRING)
+        .field_attribute("request_id", OPTIONAL_STRING)
+        .field_attribute("message_id", OPTIONAL_STRING)
         .out_dir("src/wire")
         .compile(&["src/wire/wire.proto"], &["src/wire"])
         .unwrap();
@@ -1,6 +1,9 @@
 use chat_demo::gui::gui;
-use chat_demo::protocol::{convert_err, CERT_PEM};
-use chat_demo::{ClientMessage, ServerMessage};
+use chat_demo::protocol::{
+    convert_err, framed, request_encoding, FrameReader, FrameWriter, CERT_PEM,
+};
+use chat_demo::{ClientMessage, Encoding, ServerMessage};
+use futures::{SinkExt, StreamExt};
 use s2n_quic::client::Connect;
 use s2n_quic::stream::{ReceiveStream, SendStream};
 use s2n_quic::Client;
@@ -26,7 +29,15 @@ async fn main() -> anyhow::Result<()> {
 
     conn.keep_alive(true).map_err(convert_err)?;
 
+    // protobuf unless started with --json
+    let encoding = match std::env::args().any(|arg| arg == "--json") {
+        true => Encoding::Json,
+        false => Encoding::Protobuf,
+    };
     let (recevier, sender) = conn.open_bidirectional_stream().await?.split();
+    let (mut recevier, mut sender) = framed(recevier, sender);
+    request_encoding(&mut recevier, &mut sender, encoding).await?;
+    info!("encoding {}", encoding.name());
     let (client_tx, client_rx) = mpsc::channel(CHANNEL_SIZE);
     let (server_tx, server_rx) = mpsc::channel(CHANNEL_SIZE);
     let mut tasks = Vec::with_capacity(3);
@@ -36,9 +47,9 @@ async fn main() -> anyhow::Result<()> {
         Ok(())
     }));
     // read loop
-    tasks.push(tokio::spawn(read_loop(recevier, server_tx)));
+    tasks.push(tokio::spawn(read_loop(recevier, encoding, server_tx)));
     // write loop
-    tasks.push(tokio::spawn(write_loop(sender, client_rx)));
+    tasks.push(tokio::spawn(write_loop(sender, encoding, client_rx)));
     info!("run ...");
     // tasks select_all
     let result = futures::future::select_all(tasks).await.0;
@@ -54,21 +65,23 @@ fn view(tx: mpsc::Sender<ClientMessage>, rx: mpsc::Receiver<ServerMessage>) {
 }
 
 async fn write_loop(
-    mut sender: SendStream,
+    mut sender: FrameWriter<SendStream>,
+    encoding: Encoding,
     mut rx: mpsc::Receiver<ClientMessage>,
 ) -> anyhow::Result<()> {
     while let Some(msg) = rx.recv().await {
-        sender.send(msg.try_into()?).await?;
+        sender.send(encoding.encode(&msg)?).await?;
     }
     Ok(())
 }
 
 async fn read_loop(
-    mut receiver: ReceiveStream,
+    mut receiver: FrameReader<ReceiveStream>,
+    encoding: Encoding,
     tx: mpsc::Sender<ServerMessage>,
 ) -> anyhow::Result<()> {
-    while let Some(msg) = receiver.receive().await? {
-        let msg: ServerMessage = msg.try_into()?;
+    while let Some(frame) = receiver.next().await {
+        let msg: ServerMessage = encoding.decode(&frame?)?;
         tx.send(msg).await?;
     }
     Ok(())
@@ -1,9 +1,12 @@
 let socket = null;
+// acks and errors carry back the request_id of the command
+let request_id = 0;
 
 document.getElementById("btn_send").onclick = send_message;
 
 document.getElementById("login").addEventListener("click", login);
 document.getElementById("btn_sub").addEventListener("click", subscription);
+document.getElementById("btn_create").addEventListener("click", create_room);
 
 ws_open()
 
@@ -21,7 +24,13 @@ function ws_open() {
             console.log("ws message", e);
             let msg = JSON.parse(e.data);
             console.log(msg);
-            append_messages(msg)
+            if (msg.event && msg.event.ack) {
+                console.log(`request ${msg.request_id} ok`);
+            } else if (msg.event && msg.event.typing) {
+                console.log(`${msg.user_name} typing: ${msg.event.typing.active}`);
+            } else if (msg.event) {
+                append_messages(msg)
+            }
         }
     };
 }
@@ -30,6 +39,9 @@ function send_message() {
     let msg = {
         topic: get_topic(),
         message: { send_message: document.getElementById("input_message").value },
+        request_id: next_request_id(),
+        // idempotency key, a retry of this message would reuse it
+        message_id: crypto.randomUUID(),
     };
     let data = JSON.stringify(msg);
 
@@ -38,21 +50,57 @@ function send_message() {
 
 // show message to list
 function append_messages(msg) {
-    // <tr>
-    //   <td>Shining Star</td>
-    //   <td>Earth, Wind, and Fire</td>
-    //   <td>1975</td>
-    // </tr>
-
-    // append child
-    const child = `
-    <tr>
-    <td>${msg.topic}</td>
-    <td>${msg.sequence}</td>
-    <td>${msg.message}</td>
-  </tr>`
-
-    document.getElementById("table_messages").insertAdjacentHTML("beforeend", child);
+    // names and texts come from other clients, set as text and never parsed as html
+    const row = document.createElement("tr");
+    const cells = [
+        msg.topic,
+        msg.sequence,
+        new Date(msg.timestamp).toLocaleTimeString(),
+        msg.user_name,
+        event_text(msg.event),
+    ];
+    for (const text of cells) {
+        const cell = document.createElement("td");
+        cell.textContent = text;
+        row.appendChild(cell);
+    }
+    document.getElementById("table_messages").appendChild(row);
+}
+
+// event to display text
+function event_text(event) {
+    if (event.chat_message !== undefined) {
+        return event.chat_message;
+    }
+    if (event.member_joined) {
+        return "joined";
+    }
+    if (event.member_left) {
+        return "left";
+    }
+    if (event.presence) {
+        // PresenceStatus
+        return ["offline", "online", "idle"][event.presence.status || 0];
+    }
+    if (event.gap) {
+        return `missed messages ${event.gap.from_sequence}..${event.gap.to_sequence}`;
+    }
+    if (event.room_deleted) {
+        return "room deleted";
+    }
+    if (event.error) {
+        return `error: ${event.error.message}`;
+    }
+    if (event.direct_message) {
+        return `@${event.direct_message.to_user}: ${event.direct_message.text}`;
+    }
+    if (event.kicked) {
+        return `kicked: ${event.kicked.reason}`;
+    }
+    if (event.going_away) {
+        return `server going away: ${event.going_away.reason}`;
+    }
+    return JSON.stringify(event);
 }
 
 // subscribe topic
@@ -61,6 +109,7 @@ function subscription() {
     let msg = {
         topic: get_topic(),
         message: { join_room: {} },
+        request_id: next_request_id(),
     };
     let data = JSON.stringify(msg);
     console.log(data);
@@ -68,6 +117,21 @@ function subscription() {
     socket.send(data);
 }
 
+// create a room, the creator owns and joins it
+function create_room() {
+    let msg = {
+        topic: get_topic(),
+        message: { create_room: {} },
+        request_id: next_request_id(),
+    };
+    socket.send(JSON.stringify(msg));
+}
+
+function next_request_id() {
+    request_id += 1;
+    return String(request_id);
+}
+
 function get_topic() {
     return document.getElementById("subscription").value;
 }
@@ -86,6 +150,7 @@ function login() {
     let msg = {
         topic: "",
         message: { login: { name } },
+        request_id: next_request_id(),
     };
     let data = JSON.stringify(msg);
     console.log(data);
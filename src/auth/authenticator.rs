@@ -0,0 +1,39 @@
+// 登录认证
+
+use crate::session::ChatError;
+use crate::wire::Login;
+
+pub trait Authenticator: Send + Sync {
+    // user name of the session when the credentials are valid
+    fn authenticate(&self, login: &Login) -> anyhow::Result<String>;
+}
+
+// trusts the name, only rejects empty names
+pub struct AnyNameAuthenticator;
+
+impl Authenticator for AnyNameAuthenticator {
+    fn authenticate(&self, login: &Login) -> anyhow::Result<String> {
+        let name = login.name.trim();
+        if name.is_empty() {
+            return Err(ChatError::InvalidCredentials(login.name.clone()).into());
+        }
+        Ok(name.to_string())
+    }
+}
+
+// what to do when a user logs in while already having a session
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateLogin {
+    // keep all sessions of the user
+    #[default]
+    Allow,
+    // refuse the new login
+    Reject,
+    // close the older sessions
+    KickOld,
+}
+
+// compare without leaking the position of the first difference
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,140 @@
+// 文件用户认证，每行 `name:rounds:salt:pbkdf2_hmac_sha256(password, salt, rounds)`，# 开头为注释
+
+use crate::auth::{constant_time_eq, Authenticator};
+use crate::session::ChatError;
+use crate::utils::generate_uid;
+use crate::wire::Login;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::path::Path;
+
+// work factor for new hashes, lines keep their own so it can be raised later
+pub const PASSWORD_ROUNDS: u32 = 600_000;
+
+struct PasswordHash {
+    rounds: u32,
+    salt: String,
+    hash: String,
+}
+
+pub struct FileAuthenticator {
+    // key: user name
+    users: HashMap<String, PasswordHash>,
+    // checked for unknown names, so they take as long as known ones
+    dummy: PasswordHash,
+}
+
+impl FileAuthenticator {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let mut users = HashMap::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.splitn(4, ':').collect::<Vec<_>>()[..] {
+                [name, rounds, salt, hash] => {
+                    let rounds = match rounds.parse() {
+                        Ok(rounds) if rounds > 0 => rounds,
+                        _ => return Err(anyhow::anyhow!("invalid rounds on user line {}", i + 1)),
+                    };
+                    let hash = PasswordHash {
+                        rounds,
+                        salt: salt.to_string(),
+                        hash: hash.to_lowercase(),
+                    };
+                    users.insert(name.to_string(), hash);
+                }
+                _ => return Err(anyhow::anyhow!("invalid user line {}", i + 1)),
+            }
+        }
+        let dummy = PasswordHash {
+            rounds: users
+                .values()
+                .map(|user| user.rounds)
+                .max()
+                .unwrap_or(PASSWORD_ROUNDS),
+            salt: generate_uid(),
+            hash: String::new(),
+        };
+        Ok(FileAuthenticator { users, dummy })
+    }
+}
+
+// a user file line with a new salt and `PASSWORD_ROUNDS`
+pub fn user_line(name: &str, password: &str) -> String {
+    user_line_with_rounds(name, password, PASSWORD_ROUNDS)
+}
+
+fn user_line_with_rounds(name: &str, password: &str, rounds: u32) -> String {
+    let salt = generate_uid();
+    let hash = hash_password(&salt, password, rounds);
+    format!("{name}:{rounds}:{salt}:{hash}")
+}
+
+// hex pbkdf2 hmac-sha256 of password, used to write the user file
+pub fn hash_password(salt: &str, password: &str, rounds: u32) -> String {
+    let mut hash = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt.as_bytes(), rounds, &mut hash);
+    hex::encode(hash)
+}
+
+impl Authenticator for FileAuthenticator {
+    fn authenticate(&self, login: &Login) -> anyhow::Result<String> {
+        let user = self.users.get(&login.name);
+        let expected = user.unwrap_or(&self.dummy);
+        let hash = hash_password(&expected.salt, &login.password, expected.rounds);
+        // the dummy hash is empty and never matches
+        match constant_time_eq(hash.as_bytes(), expected.hash.as_bytes()) {
+            true if user.is_some() => Ok(login.name.clone()),
+            _ => Err(ChatError::InvalidCredentials(login.name.clone()).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::file::user_line_with_rounds;
+    use crate::auth::{hash_password, Authenticator, FileAuthenticator};
+    use crate::wire::Login;
+
+    #[test]
+    fn file_authenticate() {
+        let content = format!(
+            "# users\nalice:10:s1:{}\n",
+            hash_password("s1", "secret", 10)
+        );
+        let auth = FileAuthenticator::parse(&content).unwrap();
+
+        let login = |name: &str, password: &str| Login {
+            name: name.into(),
+            password: password.into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            auth.authenticate(&login("alice", "secret")).unwrap(),
+            "alice"
+        );
+        assert!(auth.authenticate(&login("alice", "wrong")).is_err());
+        assert!(auth.authenticate(&login("bob", "secret")).is_err());
+        assert!(FileAuthenticator::parse("alice").is_err());
+        assert!(FileAuthenticator::parse("alice:0:s1:00").is_err());
+    }
+
+    #[test]
+    fn file_user_line() {
+        let line = user_line_with_rounds("alice", "secret", 10);
+        assert!(line.starts_with("alice:10:"), "{line}");
+        let auth = FileAuthenticator::parse(&line).unwrap();
+        let login = Login {
+            name: "alice".into(),
+            password: "secret".into(),
+            ..Default::default()
+        };
+        assert_eq!(auth.authenticate(&login).unwrap(), "alice");
+    }
+}
@@ -0,0 +1,9 @@
+mod authenticator;
+mod file;
+mod signed;
+mod token;
+
+pub use self::authenticator::*;
+pub use self::file::*;
+pub use self::signed::*;
+pub use self::token::*;
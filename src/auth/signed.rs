@@ -0,0 +1,113 @@
+// 服务端签发的 token，`base64(expires:user_name).base64(hmac_sha256)`，无需查询即可校验
+
+use crate::auth::constant_time_eq;
+use crate::session::ChatError;
+use crate::utils::timestamp_millis;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::Duration;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub struct TokenSigner {
+    secret: Vec<u8>,
+    ttl: Duration,
+}
+
+impl TokenSigner {
+    pub fn new(secret: impl Into<Vec<u8>>, ttl: Duration) -> Self {
+        TokenSigner {
+            secret: secret.into(),
+            ttl,
+        }
+    }
+
+    pub fn issue(&self, user_name: &str) -> String {
+        self.encode(timestamp_millis() / 1000 + self.ttl.as_secs(), user_name)
+    }
+
+    // `expires` in unix seconds
+    fn encode(&self, expires: u64, user_name: &str) -> String {
+        let payload = format!("{expires}:{user_name}");
+        format!(
+            "{}.{}",
+            base64::encode_config(&payload, base64::URL_SAFE_NO_PAD),
+            base64::encode_config(self.sign(payload.as_bytes()), base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    // user name of a valid, unexpired token
+    pub fn verify(&self, token: &str) -> anyhow::Result<String> {
+        let (expires, user_name) = self.decode(token).ok_or(ChatError::InvalidToken)?;
+        if expires < timestamp_millis() / 1000 {
+            return Err(ChatError::TokenExpired.into());
+        }
+        Ok(user_name)
+    }
+
+    // (expires, user_name) of a token with a valid signature
+    fn decode(&self, token: &str) -> Option<(u64, String)> {
+        let (payload, signature) = token.split_once('.')?;
+        let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+
+        let mut mac = self.mac();
+        mac.update(&payload);
+        mac.verify_slice(&signature).ok()?;
+
+        let payload = String::from_utf8(payload).ok()?;
+        let (expires, user_name) = payload.split_once(':')?;
+        Some((expires.parse().ok()?, user_name.to_string()))
+    }
+
+    fn sign(&self, payload: &[u8]) -> Vec<u8> {
+        let mut mac = self.mac();
+        mac.update(payload);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("hmac accepts any key length")
+    }
+}
+
+// token of an `Authorization: Bearer <token>` value
+pub fn bearer_token(value: &str) -> Option<&str> {
+    value
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+// an `Authorization` value carrying exactly `expected`, for the admin apis
+pub(crate) fn bearer_matches(authorization: Option<&str>, expected: &str) -> bool {
+    authorization
+        .and_then(bearer_token)
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::{bearer_token, TokenSigner};
+    use crate::session::ChatError;
+    use crate::utils::timestamp_millis;
+    use std::time::Duration;
+
+    #[test]
+    fn signed_token() {
+        let signer = TokenSigner::new("secret", Duration::from_secs(60));
+        let token = signer.issue("alice:1");
+        assert_eq!(signer.verify(&token).unwrap(), "alice:1");
+
+        let other = TokenSigner::new("other", Duration::from_secs(60));
+        assert!(other.verify(&token).is_err());
+        assert!(signer.verify("garbage").is_err());
+
+        let expired = signer.encode(timestamp_millis() / 1000 - 1, "alice");
+        let err = signer.verify(&expired).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(ChatError::TokenExpired)));
+
+        assert_eq!(bearer_token("Bearer abc"), Some("abc"));
+        assert_eq!(bearer_token("Basic abc"), None);
+    }
+}
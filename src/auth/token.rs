@@ -0,0 +1,27 @@
+// 静态 token 认证
+
+use crate::auth::{constant_time_eq, Authenticator};
+use crate::session::ChatError;
+use crate::wire::Login;
+use std::collections::HashMap;
+
+pub struct StaticTokenAuthenticator {
+    // key: token, value: user name
+    tokens: HashMap<String, String>,
+}
+
+impl StaticTokenAuthenticator {
+    pub fn new(tokens: HashMap<String, String>) -> Self {
+        StaticTokenAuthenticator { tokens }
+    }
+}
+
+impl Authenticator for StaticTokenAuthenticator {
+    fn authenticate(&self, login: &Login) -> anyhow::Result<String> {
+        self.tokens
+            .iter()
+            .find(|(token, _)| constant_time_eq(token.as_bytes(), login.token.as_bytes()))
+            .map(|(_, user_name)| user_name.clone())
+            .ok_or_else(|| ChatError::InvalidCredentials(login.name.clone()).into())
+    }
+}
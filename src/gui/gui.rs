@@ -1,11 +1,28 @@
 use crate::client_message::Message;
-use crate::{ClientMessage, JoinRoom, Login, SendMessage, ServerMessage};
+use crate::{
+    generate_uid, ClientMessage, CreateRoom, Event, JoinRoom, ListMembers, ListRooms, Login,
+    PresenceStatus, SendMessage, ServerMessage, Typing,
+};
 use fltk::{app, group::Flex, prelude::*, window, *};
 use fltk_table::{SmartTable, TableOpts};
+use std::cell::Cell;
+use std::collections::{BTreeSet, HashMap};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tracing::info;
 
+// request id of the next command, acks and errors carry it back
+static REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+// width of the room and member lists right of the message table
+const PANEL_WIDTH: i32 = 180;
+
+// typing starts are repeated this often while typing, the server expires them after 5s
+const TYPING_REFRESH: Duration = Duration::from_secs(3);
+
 pub struct View {
     pub width: i32,
     pub height: i32,
@@ -26,7 +43,8 @@ impl View {
     }
 
     // message dispatch
-    fn message_dispatch(tx: mpsc::Sender<ClientMessage>, msg: ClientMessage) {
+    fn message_dispatch(tx: mpsc::Sender<ClientMessage>, mut msg: ClientMessage) {
+        msg.request_id = REQUEST_ID.fetch_add(1, Ordering::Relaxed).to_string();
         tokio::task::spawn_blocking(move || {
             info!("send message: {msg:?}");
             tx.blocking_send(msg)?;
@@ -57,7 +75,11 @@ impl View {
                     tx.clone(),
                     ClientMessage {
                         topic: "".to_string(),
-                        message: Some(Message::Login(Login { name: val })),
+                        message: Some(Message::Login(Login {
+                            name: val,
+                            ..Default::default()
+                        })),
+                        ..Default::default()
                     },
                 );
             }
@@ -78,21 +100,33 @@ impl View {
             .with_size(5, 5)
             .with_label("subscription");
 
-        let tx = self.tx.clone();
-        let subscribe_topic = self.subscribe_topic.clone();
-        btn.set_callback(move |_| {
-            let val = input.value();
-            if !val.is_empty() {
-                *subscribe_topic.write().unwrap() = val.clone().into();
-                Self::message_dispatch(
-                    tx.clone(),
-                    ClientMessage {
-                        topic: val,
-                        message: Some(Message::JoinRoom(JoinRoom {})),
-                    },
-                );
-            }
-        });
+        let mut create_btn = button::Button::default()
+            .with_size(5, 5)
+            .with_label("create");
+
+        for (btn, create) in [(&mut btn, false), (&mut create_btn, true)] {
+            let tx = self.tx.clone();
+            let subscribe_topic = self.subscribe_topic.clone();
+            let input = input.clone();
+            btn.set_callback(move |_| {
+                let val = input.value();
+                if !val.is_empty() {
+                    *subscribe_topic.write().unwrap() = val.clone().into();
+                    let message = match create {
+                        true => Message::CreateRoom(CreateRoom::default()),
+                        false => Message::JoinRoom(JoinRoom {}),
+                    };
+                    Self::message_dispatch(
+                        tx.clone(),
+                        ClientMessage {
+                            topic: val,
+                            message: Some(message),
+                            ..Default::default()
+                        },
+                    );
+                }
+            });
+        }
 
         subs
     }
@@ -112,6 +146,32 @@ impl View {
             .with_size(0, 0)
             .with_label("submit");
 
+        // last typing start sent, the server ends typing when the message is sent
+        let typing_sent: Rc<Cell<Option<Instant>>> = Rc::new(Cell::new(None));
+
+        let tx = self.tx.clone();
+        let subscribe_topic = self.subscribe_topic.clone();
+        let sent = typing_sent.clone();
+        input.set_trigger(enums::CallbackTrigger::Changed);
+        input.set_callback(move |input| {
+            let active = !input.value().is_empty();
+            let due = match sent.get() {
+                None => active,
+                Some(at) => !active || at.elapsed() >= TYPING_REFRESH,
+            };
+            if due {
+                sent.set(active.then(Instant::now));
+                Self::message_dispatch(
+                    tx.clone(),
+                    ClientMessage {
+                        topic: subscribe_topic.read().unwrap().to_string(),
+                        message: Some(Message::Typing(Typing { active })),
+                        ..Default::default()
+                    },
+                );
+            }
+        });
+
         let tx = self.tx.clone();
         let subscribe_topic = self.subscribe_topic.clone();
         btn.set_callback(move |_| {
@@ -124,15 +184,60 @@ impl View {
                     ClientMessage {
                         topic: topic.to_string(),
                         message: Some(SendMessage(val)),
+                        // a retry of this message would reuse the id
+                        message_id: generate_uid(),
+                        ..Default::default()
                     },
                 );
                 input.set_value("");
+                typing_sent.set(None);
             }
         });
 
         msg
     }
 
+    // room list with member counts, selecting a room lists its members below
+    fn rooms_panel(&self) -> (browser::HoldBrowser, browser::HoldBrowser) {
+        let x = self.width - 15 - PANEL_WIDTH;
+        let list_height = (self.height - 230) / 2;
+
+        let mut refresh = button::Button::new(x, 180, PANEL_WIDTH, 25, "rooms");
+        let mut rooms = browser::HoldBrowser::new(x, 210, PANEL_WIDTH, list_height, None);
+        frame::Frame::new(x, 215 + list_height, PANEL_WIDTH, 20, "members");
+        let members =
+            browser::HoldBrowser::new(x, 240 + list_height, PANEL_WIDTH, list_height, None);
+
+        let tx = self.tx.clone();
+        refresh.set_callback(move |_| {
+            Self::message_dispatch(
+                tx.clone(),
+                ClientMessage {
+                    message: Some(Message::ListRooms(ListRooms {})),
+                    ..Default::default()
+                },
+            );
+        });
+
+        let tx = self.tx.clone();
+        rooms.set_callback(move |b| {
+            // lines are "topic (members)"
+            if let Some(line) = b.selected_text() {
+                let topic = line.rsplit_once(" (").map_or(line.as_str(), |(t, _)| t);
+                Self::message_dispatch(
+                    tx.clone(),
+                    ClientMessage {
+                        topic: topic.to_string(),
+                        message: Some(Message::ListMembers(ListMembers {})),
+                        ..Default::default()
+                    },
+                );
+            }
+        });
+
+        (rooms, members)
+    }
+
     pub fn show(&mut self, mut rx: mpsc::Receiver<ServerMessage>) {
         let app = app::App::default();
         let mut wind = window::Window::default()
@@ -146,16 +251,18 @@ impl View {
         self.subscription().end();
         self.send_message().end();
 
+        let (mut rooms, mut members) = self.rooms_panel();
+
         let mut table = SmartTable::default()
-            .with_size(&self.width - 25, &self.height - 140)
+            .with_size(&self.width - 30 - PANEL_WIDTH, &self.height - 215)
             .with_opts(TableOpts {
                 rows: 1,
-                cols: 3,
+                cols: 6,
                 ..Default::default()
             })
             .with_pos(10, 180);
 
-        let col_headers = ["topic", "sequence", "message"];
+        let col_headers = ["topic", "sequence", "time", "user", "session", "message"];
         table.set_col_header_value(0, "topic");
         col_headers.iter().enumerate().for_each(|(i, v)| {
             table.set_col_header_value(i as i32, v);
@@ -163,20 +270,91 @@ impl View {
 
         table.end();
 
+        // "x is typing…" of the current topic
+        let mut typing_label = frame::Frame::default()
+            .with_size(&self.width - 30 - PANEL_WIDTH, 25)
+            .with_pos(10, &self.height - 30)
+            .with_align(enums::Align::Left | enums::Align::Inside);
+
         // wind 中间的东西会加入 wind
         wind.end();
         // wind builder
         wind.show();
 
+        let subscribe_topic = self.subscribe_topic.clone();
         tokio::task::spawn_blocking(move || {
             let mut first = true;
+            // key: topic, value: users typing
+            let mut typing: HashMap<String, BTreeSet<String>> = HashMap::new();
             while let Some(msg) = rx.blocking_recv() {
                 info!("recv {:?}", msg.topic);
-                match msg.message {
+                let users = typing.entry(msg.topic.clone()).or_default();
+                match &msg.event {
+                    Some(Event::Typing(t)) if t.active => {
+                        users.insert(msg.user_name.clone());
+                    }
+                    Some(Event::Typing(_)) | Some(Event::MemberLeft(_)) => {
+                        users.remove(&msg.user_name);
+                    }
+                    _ => {}
+                }
+                let current = typing.get(&*subscribe_topic.read().unwrap());
+                typing_label.set_label(&match current {
+                    Some(users) if !users.is_empty() => {
+                        let names: Vec<&str> = users.iter().map(String::as_str).collect();
+                        format!("{} typing…", names.join(", "))
+                    }
+                    _ => String::new(),
+                });
+
+                let text = match &msg.event {
+                    Some(Event::ChatMessage(data)) => Some(data.clone()),
+                    Some(Event::MemberJoined(_)) => Some("joined".to_string()),
+                    Some(Event::MemberLeft(_)) => Some("left".to_string()),
+                    Some(Event::RoomDeleted(_)) => Some("room deleted".to_string()),
+                    Some(Event::Gap(gap)) => Some(format!(
+                        "missed messages {}..={}",
+                        gap.from_sequence, gap.to_sequence
+                    )),
+                    Some(Event::Presence(p)) => PresenceStatus::from_i32(p.status)
+                        .map(|status| format!("{status:?}").to_lowercase()),
+                    Some(Event::Error(e)) => {
+                        Some(format!("error: {} (request {})", e.message, msg.request_id))
+                    }
+                    Some(Event::Kicked(k)) => Some(format!("kicked: {}", k.reason)),
+                    Some(Event::GoingAway(g)) => Some(format!("server going away: {}", g.reason)),
+                    Some(Event::DirectMessage(dm)) => Some(format!("@{}: {}", dm.to_user, dm.text)),
+                    Some(Event::Ack(ack)) => {
+                        info!("request {} ok", msg.request_id);
+                        if let Some(list) = &ack.room_list {
+                            rooms.clear();
+                            for room in &list.rooms {
+                                rooms.add(&format!("{} ({})", room.topic, room.members));
+                            }
+                        }
+                        if let Some(list) = &ack.member_list {
+                            members.clear();
+                            for member in &list.members {
+                                members.add(member);
+                            }
+                        }
+                        None
+                    }
+                    _ => None,
+                };
+                match text {
                     None => {}
                     Some(data) => {
                         let seq = msg.sequence.to_string();
-                        let row = &vec![msg.topic.as_str(), seq.as_str(), data.as_str()];
+                        let time = format_time(msg.timestamp);
+                        let row = &vec![
+                            msg.topic.as_str(),
+                            seq.as_str(),
+                            time.as_str(),
+                            msg.user_name.as_str(),
+                            msg.session_id.as_str(),
+                            data.as_str(),
+                        ];
                         table.append_row("", row);
                         if first {
                             table.remove_row(0);
@@ -191,3 +369,14 @@ impl View {
         app.run().unwrap();
     }
 }
+
+// unix millis to HH:MM:SS (UTC)
+fn format_time(timestamp: u64) -> String {
+    let secs = timestamp / 1000;
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
@@ -1,10 +1,16 @@
+mod auth;
 #[cfg(feature = "gui")]
 pub mod gui;
 pub mod protocol;
+mod server;
 mod session;
+mod storage;
 mod utils;
 mod wire;
 
+pub use self::auth::*;
+pub use self::server::*;
 pub use self::session::*;
+pub use self::storage::*;
 pub use self::utils::*;
 pub use self::wire::*;
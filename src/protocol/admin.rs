@@ -0,0 +1,202 @@
+// http 管理和查询接口，挂在 axum router 上，json 与 grpc ChatAdmin 的消息相同
+// 需要 SessionStore 和 TopicStore 的 Extension，没有 admin token 时不做鉴权，只应监听内网地址
+
+use crate::auth::bearer_matches;
+use crate::session::{ChatError, SessionStore, TopicStore};
+use crate::wire::{
+    ChatMessage, PublishRequest, PublishResponse, RoomInfo, ServerMessage, SessionInfo,
+};
+use axum::body::Body;
+use axum::extract::Path;
+use axum::http::header::AUTHORIZATION;
+use axum::http::{Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Extension, Json, Router};
+use std::sync::Arc;
+
+pub fn admin_router() -> Router {
+    Router::new()
+        .route("/topics", get(list_topics))
+        .route("/topics/:id/members", get(list_members))
+        .route("/topics/:id/messages", post(publish))
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/:id", delete(disconnect))
+}
+
+// every request of `router` needs `Authorization: Bearer <token>`, unchanged without a token
+pub fn with_admin_token(router: Router, token: Option<String>) -> Router {
+    let Some(token) = token else {
+        return router;
+    };
+    router.layer(middleware::from_fn(
+        move |request: Request<Body>, next: Next<Body>| {
+            let authorization = request.headers().get(AUTHORIZATION);
+            let authorized = bearer_matches(authorization.and_then(|v| v.to_str().ok()), &token);
+            async move {
+                match authorized {
+                    true => next.run(request).await,
+                    false => StatusCode::UNAUTHORIZED.into_response(),
+                }
+            }
+        },
+    ))
+}
+
+async fn list_topics(Extension(topics): Extension<Arc<TopicStore>>) -> Json<Vec<RoomInfo>> {
+    Json(topics.list_rooms())
+}
+
+async fn list_members(
+    Path(id): Path<String>,
+    Extension(topics): Extension<Arc<TopicStore>>,
+) -> Result<Json<Vec<String>>, Response> {
+    topics.members(&id).map(Json).map_err(error_response)
+}
+
+async fn publish(
+    Path(id): Path<String>,
+    Extension(topics): Extension<Arc<TopicStore>>,
+    Json(mut request): Json<PublishRequest>,
+) -> Result<Json<PublishResponse>, Response> {
+    request.topic = id;
+    publish_message(&topics, request)
+        .map(Json)
+        .map_err(error_response)
+}
+
+// shared with the grpc ChatAdmin
+pub(crate) fn publish_message(
+    topics: &TopicStore,
+    request: PublishRequest,
+) -> anyhow::Result<PublishResponse> {
+    let message = ServerMessage {
+        event: Some(ChatMessage(request.text)),
+        user_name: request.user_name,
+        message_id: request.message_id,
+        ..Default::default()
+    };
+    let sequence = topics.send_message(&request.topic, message)?;
+    Ok(PublishResponse { sequence })
+}
+
+pub(crate) fn session_infos(sessions: &SessionStore) -> Vec<SessionInfo> {
+    sessions
+        .list()
+        .into_iter()
+        .map(|sess| SessionInfo {
+            user_name: sess.user_name(),
+            topics: sess.subscribed_topics(),
+            id: sess.id,
+        })
+        .collect()
+}
+
+async fn list_sessions(
+    Extension(sessions): Extension<Arc<SessionStore>>,
+) -> Json<Vec<SessionInfo>> {
+    Json(session_infos(&sessions))
+}
+
+async fn disconnect(
+    Path(id): Path<String>,
+    Extension(sessions): Extension<Arc<SessionStore>>,
+) -> StatusCode {
+    match sessions.disconnect(&id, "disconnected by admin") {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    }
+}
+
+fn error_response(err: anyhow::Error) -> Response {
+    let status = match err.downcast_ref::<ChatError>() {
+        Some(ChatError::TopicNotFound(_)) => StatusCode::NOT_FOUND,
+        Some(_) => StatusCode::BAD_REQUEST,
+        None => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, err.to_string()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::test_utils::{request, serve};
+    use crate::protocol::{admin_router, with_admin_token};
+    use crate::session::{Session, SessionStore, TopicStore};
+    use crate::wire::{CreateRoom, Event, PublishResponse, RoomInfo, SessionInfo};
+    use axum::Extension;
+    use std::sync::Arc;
+    use tokio::sync::mpsc::channel;
+
+    #[tokio::test]
+    async fn admin_api() {
+        let sessions = Arc::new(SessionStore::new());
+        let topics = Arc::new(TopicStore::new());
+        let app = admin_router()
+            .layer(Extension(sessions.clone()))
+            .layer(Extension(topics.clone()));
+        let addr = serve(app);
+
+        let (tx, mut rx) = channel(4);
+        let sess = Session::new("s1".into(), sessions.clone(), topics.clone(), tx);
+        sessions
+            .add_with_user(sess.clone(), Some("bob".into()))
+            .unwrap();
+        topics
+            .create("room", "bob".into(), &CreateRoom::default())
+            .unwrap();
+        let _sub = topics.subscribe("bob".into(), "room").unwrap();
+
+        let (status, body) = request(addr, "GET", "/topics", "", "").await;
+        assert_eq!(status, 200);
+        let rooms: Vec<RoomInfo> = serde_json::from_str(&body).unwrap();
+        assert_eq!(rooms[0].topic, "room");
+        assert_eq!(rooms[0].members, 1);
+
+        let (status, body) = request(addr, "GET", "/topics/room/members", "", "").await;
+        assert_eq!((status, body.as_str()), (200, r#"["bob"]"#));
+        let (status, _) = request(addr, "GET", "/topics/nowhere/members", "", "").await;
+        assert_eq!(status, 404);
+
+        let publish = r#"{"user_name":"bot","text":"hello"}"#;
+        let (status, body) = request(addr, "POST", "/topics/room/messages", "", publish).await;
+        assert_eq!(status, 200);
+        let response: PublishResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(topics.history("room", 0, 10).unwrap().len(), 1);
+        assert!(response.sequence > 0);
+
+        let (status, body) = request(addr, "GET", "/sessions", "", "").await;
+        assert_eq!(status, 200);
+        let infos: Vec<SessionInfo> = serde_json::from_str(&body).unwrap();
+        assert_eq!(infos.len(), 1);
+        assert_eq!(
+            (infos[0].id.as_str(), infos[0].user_name.as_str()),
+            ("s1", "bob")
+        );
+
+        let (status, _) = request(addr, "DELETE", "/sessions/s1", "", "").await;
+        assert_eq!(status, 204);
+        assert!(sessions.find_by_user("bob").is_empty());
+        let kicked = rx.recv().await.unwrap();
+        assert!(matches!(kicked.event, Some(Event::Kicked(_))));
+        let (status, _) = request(addr, "DELETE", "/sessions/s1", "", "").await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn admin_token() {
+        let app = with_admin_token(admin_router(), Some("secret".into()))
+            .layer(Extension(Arc::new(SessionStore::new())))
+            .layer(Extension(Arc::new(TopicStore::new())));
+        let addr = serve(app);
+
+        let (status, _) = request(addr, "GET", "/topics", "", "").await;
+        assert_eq!(status, 401);
+        let wrong = "authorization: Bearer guess\r\n";
+        let (status, _) = request(addr, "GET", "/topics", wrong, "").await;
+        assert_eq!(status, 401);
+        let right = "authorization: Bearer secret\r\n";
+        let (status, body) = request(addr, "GET", "/topics", right, "").await;
+        assert_eq!((status, body.as_str()), (200, "[]"));
+    }
+}
@@ -0,0 +1,239 @@
+// 字节流传输（quic, tcp）的分帧
+// 长度前缀：第一帧是编码名，服务端原样返回表示接受，之后每帧一条消息
+// 按行：每行一条 json 消息
+
+use crate::protocol::{serve_with_login, Connection, MessageSink, MessageStream};
+use crate::wire::{ClientMessage, Encoding, ServerMessage};
+use crate::{SessionStore, TopicStore};
+use bytes::Bytes;
+use futures::{future, SinkExt, StreamExt};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec, LinesCodec};
+
+pub type FrameReader<R> = FramedRead<R, LengthDelimitedCodec>;
+pub type FrameWriter<W> = FramedWrite<W, LengthDelimitedCodec>;
+
+pub fn framed<R: AsyncRead, W: AsyncWrite>(
+    reader: R,
+    writer: W,
+) -> (FrameReader<R>, FrameWriter<W>) {
+    (
+        FramedRead::new(reader, LengthDelimitedCodec::new()),
+        FramedWrite::new(writer, LengthDelimitedCodec::new()),
+    )
+}
+
+// client side, fails when the server does not accept `encoding`
+pub async fn request_encoding<R, W>(
+    reader: &mut FrameReader<R>,
+    writer: &mut FrameWriter<W>,
+    encoding: Encoding,
+) -> anyhow::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    writer
+        .send(Bytes::from_static(encoding.name().as_bytes()))
+        .await?;
+    match reader.next().await.transpose()? {
+        Some(frame) if frame[..] == *encoding.name().as_bytes() => Ok(()),
+        _ => Err(anyhow::anyhow!("encoding {} refused", encoding.name())),
+    }
+}
+
+// server side, None when the stream ends before the first frame
+pub async fn accept_encoding<R, W>(
+    reader: &mut FrameReader<R>,
+    writer: &mut FrameWriter<W>,
+) -> anyhow::Result<Option<Encoding>>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let frame = match reader.next().await {
+        Some(frame) => frame?,
+        None => return Ok(None),
+    };
+    let name = String::from_utf8_lossy(&frame);
+    let encoding =
+        Encoding::from_name(&name).ok_or_else(|| anyhow::anyhow!("unknown encoding {name:?}"))?;
+    writer
+        .send(Bytes::from_static(encoding.name().as_bytes()))
+        .await?;
+    Ok(Some(encoding))
+}
+
+// how messages are delimited on a byte stream transport (tcp, uds)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Framing {
+    // one json message per line
+    #[default]
+    Lines,
+    // length-prefixed frames, the encoding is negotiated first
+    LengthDelimited,
+}
+
+// longest json line accepted from a client
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+// a negotiated length-delimited stream
+pub struct FramedConnection<R, W> {
+    protocol: &'static str,
+    reader: FrameReader<R>,
+    writer: FrameWriter<W>,
+    encoding: Encoding,
+}
+
+impl<R, W> Connection for FramedConnection<R, W>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+    W: AsyncWrite + Send + Unpin + 'static,
+{
+    fn protocol(&self) -> &'static str {
+        self.protocol
+    }
+
+    fn split(self) -> (MessageStream, MessageSink) {
+        let encoding = self.encoding;
+        let inbound = self
+            .reader
+            .map(move |frame| encoding.decode_client(&frame?))
+            .boxed();
+        let outbound = SinkExt::<Bytes>::sink_map_err(self.writer, anyhow::Error::from)
+            .with(move |msg: ServerMessage| future::ready(encoding.encode(&msg)));
+        (inbound, Box::pin(outbound))
+    }
+}
+
+// newline-delimited json
+pub struct LinesConnection<R, W> {
+    protocol: &'static str,
+    reader: FramedRead<R, LinesCodec>,
+    writer: FramedWrite<W, LinesCodec>,
+}
+
+impl<R, W> Connection for LinesConnection<R, W>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+    W: AsyncWrite + Send + Unpin + 'static,
+{
+    fn protocol(&self) -> &'static str {
+        self.protocol
+    }
+
+    fn split(self) -> (MessageStream, MessageSink) {
+        let inbound = self
+            .reader
+            .map(|line| ClientMessage::try_from(line?))
+            .boxed();
+        let outbound = SinkExt::<String>::sink_map_err(self.writer, anyhow::Error::from)
+            .with(|msg: ServerMessage| future::ready(String::try_from(msg)));
+        (inbound, Box::pin(outbound))
+    }
+}
+
+// serve a length-delimited stream: negotiate the encoding, then run the session
+pub async fn serve_framed<R, W>(
+    protocol: &'static str,
+    reader: R,
+    writer: W,
+    sessions: Arc<SessionStore>,
+    topics: Arc<TopicStore>,
+) -> anyhow::Result<()>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+    W: AsyncWrite + Send + Unpin + 'static,
+{
+    let (mut reader, mut writer) = framed(reader, writer);
+    let encoding = match accept_encoding(&mut reader, &mut writer).await {
+        Ok(Some(encoding)) => encoding,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            SinkExt::<Bytes>::close(&mut writer).await?;
+            return Err(e);
+        }
+    };
+    let conn = FramedConnection {
+        protocol,
+        reader,
+        writer,
+        encoding,
+    };
+    serve_with_login(conn, sessions, topics).await
+}
+
+// serve a byte stream with the given framing
+pub async fn serve_stream<S>(
+    protocol: &'static str,
+    stream: S,
+    framing: Framing,
+    sessions: Arc<SessionStore>,
+    topics: Arc<TopicStore>,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    match framing {
+        Framing::Lines => {
+            let conn = LinesConnection {
+                protocol,
+                reader: FramedRead::new(reader, LinesCodec::new_with_max_length(MAX_LINE_LENGTH)),
+                writer: FramedWrite::new(writer, LinesCodec::new()),
+            };
+            serve_with_login(conn, sessions, topics).await
+        }
+        Framing::LengthDelimited => serve_framed(protocol, reader, writer, sessions, topics).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::{accept_encoding, framed, request_encoding};
+    use crate::wire::{ClientMessage, Encoding, SendMessage};
+    use bytes::Bytes;
+    use futures::{SinkExt, StreamExt};
+
+    #[tokio::test]
+    async fn framing() {
+        let (client, server) = tokio::io::duplex(64);
+        let (client_read, client_write) = tokio::io::split(client);
+        let (server_read, server_write) = tokio::io::split(server);
+        let (mut client_reader, mut client_writer) = framed(client_read, client_write);
+        let (mut server_reader, mut server_writer) = framed(server_read, server_write);
+
+        let server = tokio::spawn(async move {
+            let encoding = accept_encoding(&mut server_reader, &mut server_writer)
+                .await
+                .unwrap()
+                .unwrap();
+            let mut received = vec![];
+            while let Some(frame) = server_reader.next().await {
+                received.push(encoding.decode::<ClientMessage>(&frame.unwrap()).unwrap());
+            }
+            (encoding, received)
+        });
+
+        let encoding = Encoding::Protobuf;
+        request_encoding(&mut client_reader, &mut client_writer, encoding)
+            .await
+            .unwrap();
+        // larger than the pipe, so frames are split on the way
+        let msg = ClientMessage {
+            topic: "room".into(),
+            message: Some(SendMessage("x".repeat(200))),
+            ..Default::default()
+        };
+        for _ in 0..3 {
+            let buf = encoding.encode(&msg).unwrap();
+            client_writer.send(buf).await.unwrap();
+        }
+        SinkExt::<Bytes>::close(&mut client_writer).await.unwrap();
+
+        let (accepted, received) = server.await.unwrap();
+        assert_eq!(accepted, Encoding::Protobuf);
+        assert_eq!(received, vec![msg.clone(), msg.clone(), msg]);
+    }
+}
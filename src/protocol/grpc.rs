@@ -1,15 +1,23 @@
+use crate::auth::bearer_matches;
+use crate::protocol::{
+    publish_message, serve_connection, session_infos, Connection, MessageSink, MessageStream,
+};
+use crate::wire::chat_admin_server::ChatAdmin;
 use crate::wire::chat_service_server::ChatService;
-use crate::wire::{ClientMessage, ServerMessage};
-use crate::{generate_uid, Session, SessionStore, TopicStore};
-use futures::future;
+use crate::wire::{
+    ClientMessage, GetHistoryRequest, GetTopicRequest, History, KickSessionRequest,
+    KickSessionResponse, ListSessionsRequest, ListTopicsRequest, PublishRequest, PublishResponse,
+    RoomList, ServerMessage, SessionList, TopicDetail,
+};
+use crate::{bearer_token, ChatError, SessionStore, TopicStore};
+use futures::{sink, StreamExt, TryStreamExt};
 use std::pin::Pin;
 use std::sync::Arc;
-use tokio::sync::mpsc::channel;
+use tokio::sync::mpsc::{channel, Sender};
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::async_trait;
 use tonic::codegen::futures_core::Stream;
 use tonic::{Request, Response, Status, Streaming};
-use tracing::info;
 use tracing::log::error;
 
 const CHANNEL_SIZE: usize = 4;
@@ -34,50 +42,248 @@ impl ChatService for ChatServer {
         &self,
         request: Request<Streaming<ClientMessage>>,
     ) -> Result<Response<Self::SendMessageStream>, Status> {
-        let (result_tx, result_rx) = channel::<Result<ServerMessage, Status>>(CHANNEL_SIZE);
-
-        let (client_tx, client_rx) = channel(CHANNEL_SIZE);
-        let (server_tx, mut server_rx) = channel(CHANNEL_SIZE);
-        let id = generate_uid();
-        info!("start grpc {id:?}");
-        let mut sess = Session::new(id.clone(), self.topics.clone(), server_tx);
-        self.sessions.add(sess.clone());
-
-        let mut tasks = vec![];
-        let sess_task =
-            tokio::spawn(async move { Ok::<(), anyhow::Error>(sess.run(client_rx).await?) });
-        tasks.push(sess_task);
-
-        let task = tokio::spawn(async move {
-            let mut stream = request.into_inner();
-            while let Some(msg) = stream.message().await? {
-                info!("Received message: {msg:?}");
-                client_tx.send(msg).await?;
-            }
+        // optional bearer token in the `authorization` metadata
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(bearer_token);
+        let user_name = self
+            .sessions
+            .verify_connection(token)
+            .map_err(|e| Status::unauthenticated(e.to_string()))?;
 
-            Ok::<(), anyhow::Error>(())
-        });
-        tasks.push(task);
-
-        let task = tokio::spawn(async move {
-            while let Some(msg) = server_rx.recv().await {
-                info!("send message: {msg:?}");
-                if let Err(e) = result_tx.send(Ok(msg)).await {
-                    error!("send message error: {e}");
-                }
+        let (result_tx, result_rx) = channel::<Result<ServerMessage, Status>>(CHANNEL_SIZE);
+        let conn = GrpcConnection {
+            inbound: request.into_inner(),
+            outbound: result_tx,
+        };
+        let sessions = self.sessions.clone();
+        let topics = self.topics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(conn, sessions, topics, user_name).await {
+                error!("grpc error: {e:?}");
             }
-            Ok::<(), anyhow::Error>(())
         });
-        tasks.push(task);
 
-        let sessions = self.sessions.clone();
+        Ok(Response::new(Box::pin(ReceiverStream::new(result_rx))))
+    }
+}
 
-        tokio::spawn(async move {
-            let result = future::select_all(tasks).await.0;
-            info!("{id:?} disconnected {result:?}");
-            sessions.remove(id);
+// for ChatAdminServer::with_interceptor, every call needs `authorization: Bearer <token>`
+// in its metadata, all pass without a token, tonic fixes the error type
+#[allow(clippy::result_large_err)]
+pub fn admin_interceptor(
+    token: Option<String>,
+) -> impl FnMut(Request<()>) -> Result<Request<()>, Status> + Clone {
+    move |request: Request<()>| {
+        let Some(token) = &token else {
+            return Ok(request);
+        };
+        let authorization = request.metadata().get("authorization");
+        match bearer_matches(authorization.and_then(|v| v.to_str().ok()), token) {
+            true => Ok(request),
+            false => Err(Status::unauthenticated("admin token required")),
+        }
+    }
+}
+
+// unary management calls against the same stores
+pub struct AdminServer {
+    sessions: Arc<SessionStore>,
+    topics: Arc<TopicStore>,
+}
+
+impl AdminServer {
+    pub fn new(sessions: Arc<SessionStore>, topics: Arc<TopicStore>) -> Self {
+        AdminServer { sessions, topics }
+    }
+}
+
+#[async_trait]
+impl ChatAdmin for AdminServer {
+    async fn list_topics(
+        &self,
+        _request: Request<ListTopicsRequest>,
+    ) -> Result<Response<RoomList>, Status> {
+        let rooms = self.topics.list_rooms();
+        Ok(Response::new(RoomList { rooms }))
+    }
+
+    async fn get_topic(
+        &self,
+        request: Request<GetTopicRequest>,
+    ) -> Result<Response<TopicDetail>, Status> {
+        let topic = request.into_inner().topic;
+        let room = self.topics.room(&topic).map_err(status)?;
+        let members = self.topics.members(&topic).map_err(status)?;
+        Ok(Response::new(TopicDetail {
+            room: Some(room),
+            members,
+        }))
+    }
+
+    async fn list_sessions(
+        &self,
+        _request: Request<ListSessionsRequest>,
+    ) -> Result<Response<SessionList>, Status> {
+        let sessions = session_infos(&self.sessions);
+        Ok(Response::new(SessionList { sessions }))
+    }
+
+    async fn kick_session(
+        &self,
+        request: Request<KickSessionRequest>,
+    ) -> Result<Response<KickSessionResponse>, Status> {
+        let request = request.into_inner();
+        let reason = match request.reason.is_empty() {
+            true => "kicked by admin",
+            false => &request.reason,
+        };
+        match self.sessions.disconnect(&request.session_id, reason) {
+            true => Ok(Response::new(KickSessionResponse {})),
+            false => Err(Status::not_found(request.session_id)),
+        }
+    }
+
+    async fn publish(
+        &self,
+        request: Request<PublishRequest>,
+    ) -> Result<Response<PublishResponse>, Status> {
+        publish_message(&self.topics, request.into_inner())
+            .map(Response::new)
+            .map_err(status)
+    }
+
+    async fn get_history(
+        &self,
+        request: Request<GetHistoryRequest>,
+    ) -> Result<Response<History>, Status> {
+        let request = request.into_inner();
+        let messages = self
+            .topics
+            .history(
+                &request.topic,
+                request.before_sequence,
+                request.limit as usize,
+            )
+            .map_err(status)?;
+        Ok(Response::new(History { messages }))
+    }
+}
+
+fn status(err: anyhow::Error) -> Status {
+    match err.downcast_ref::<ChatError>() {
+        Some(ChatError::TopicNotFound(_)) => Status::not_found(err.to_string()),
+        Some(_) => Status::invalid_argument(err.to_string()),
+        None => Status::internal(err.to_string()),
+    }
+}
+
+struct GrpcConnection {
+    inbound: Streaming<ClientMessage>,
+    outbound: Sender<Result<ServerMessage, Status>>,
+}
+
+impl Connection for GrpcConnection {
+    fn protocol(&self) -> &'static str {
+        "grpc"
+    }
+
+    fn split(self) -> (MessageStream, MessageSink) {
+        let inbound = self.inbound.map_err(anyhow::Error::from).boxed();
+        let outbound = sink::unfold(self.outbound, |tx, msg| async move {
+            tx.send(Ok(msg)).await?;
+            Ok::<_, anyhow::Error>(tx)
         });
+        (inbound, Box::pin(outbound))
+    }
+}
 
-        Ok(Response::new(Box::pin(ReceiverStream::new(result_rx))))
+#[cfg(test)]
+mod tests {
+    use crate::protocol::{admin_interceptor, AdminServer};
+    use crate::session::{Session, SessionStore, TopicStore};
+    use crate::wire::chat_admin_server::ChatAdmin;
+    use crate::wire::{
+        CreateRoom, GetHistoryRequest, GetTopicRequest, KickSessionRequest, ListSessionsRequest,
+        PublishRequest,
+    };
+    use std::sync::Arc;
+    use tokio::sync::mpsc::channel;
+    use tonic::{Code, Request};
+
+    #[tokio::test]
+    async fn chat_admin() {
+        let sessions = Arc::new(SessionStore::new());
+        let topics = Arc::new(TopicStore::new());
+        let admin = AdminServer::new(sessions.clone(), topics.clone());
+        topics
+            .create("room", "bob".into(), &CreateRoom::default())
+            .unwrap();
+
+        let publish = PublishRequest {
+            topic: "room".into(),
+            user_name: "bot".into(),
+            text: "hello".into(),
+            ..Default::default()
+        };
+        let sequence = admin.publish(Request::new(publish)).await.unwrap();
+        let history = GetHistoryRequest {
+            topic: "room".into(),
+            ..Default::default()
+        };
+        let history = admin.get_history(Request::new(history)).await.unwrap();
+        let messages = history.into_inner().messages;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].sequence, sequence.into_inner().sequence);
+
+        let topic = GetTopicRequest {
+            topic: "room".into(),
+        };
+        let detail = admin.get_topic(Request::new(topic)).await.unwrap();
+        assert_eq!(detail.into_inner().room.unwrap().owner, "bob");
+        let missing = GetTopicRequest {
+            topic: "nowhere".into(),
+        };
+        let err = admin.get_topic(Request::new(missing)).await.unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+
+        let (tx, _rx) = channel(4);
+        let sess = Session::new("s1".into(), sessions.clone(), topics.clone(), tx);
+        sessions.add_with_user(sess, Some("bob".into())).unwrap();
+        let list = admin
+            .list_sessions(Request::new(ListSessionsRequest {}))
+            .await
+            .unwrap();
+        assert_eq!(list.into_inner().sessions[0].user_name, "bob");
+        let kick = KickSessionRequest {
+            session_id: "s1".into(),
+            ..Default::default()
+        };
+        admin
+            .kick_session(Request::new(kick.clone()))
+            .await
+            .unwrap();
+        assert!(sessions.find_by_user("bob").is_empty());
+        let err = admin.kick_session(Request::new(kick)).await.unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+    }
+
+    #[test]
+    fn admin_token() {
+        let mut check = admin_interceptor(Some("secret".into()));
+        let err = check(Request::new(())).unwrap_err();
+        assert_eq!(err.code(), Code::Unauthenticated);
+        let mut request = Request::new(());
+        let value = "Bearer guess".parse().unwrap();
+        request.metadata_mut().insert("authorization", value);
+        assert!(check(request).is_err());
+        let mut request = Request::new(());
+        let value = "Bearer secret".parse().unwrap();
+        request.metadata_mut().insert("authorization", value);
+        assert!(check(request).is_ok());
+
+        assert!(admin_interceptor(None)(Request::new(())).is_ok());
     }
 }
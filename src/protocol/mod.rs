@@ -1,7 +1,25 @@
+mod admin;
+mod framing;
 mod grpc;
 mod quic;
+mod shutdown;
+mod sse;
+mod tcp;
+#[cfg(test)]
+pub(crate) mod test_utils;
+mod transport;
+#[cfg(unix)]
+mod uds;
 mod ws;
 
+pub use self::admin::*;
+pub use self::framing::*;
 pub use self::grpc::*;
 pub use self::quic::*;
+pub use self::shutdown::*;
+pub use self::sse::*;
+pub use self::tcp::*;
+pub use self::transport::*;
+#[cfg(unix)]
+pub use self::uds::*;
 pub use self::ws::*;
@@ -1,9 +1,7 @@
-use crate::wire::{ClientMessage, ServerMessage};
-use crate::{generate_uid, Session, SessionStore, TopicStore};
-use s2n_quic::stream::{BidirectionalStream, ReceiveStream, SendStream};
+use crate::protocol::{serve_framed, Shutdown};
+use crate::{SessionStore, TopicStore};
 use s2n_quic::Server;
 use std::sync::Arc;
-use tokio::sync::mpsc;
 use tracing::info;
 use tracing::log::error;
 
@@ -13,14 +11,14 @@ pub fn convert_err<E: std::error::Error>(err: E) -> anyhow::Error {
 
 pub static CERT_PEM: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/certs/cert.pem"));
 
-static KEY_PEM: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/certs/key.pem"));
-
-const CHANNEL_SIZE: usize = 4;
+pub(crate) static KEY_PEM: &str =
+    include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/certs/key.pem"));
 
 pub async fn run(
     addr: String,
     sessions: Arc<SessionStore>,
     topics: Arc<TopicStore>,
+    shutdown: Shutdown,
 ) -> anyhow::Result<()> {
     let mut server = Server::builder()
         .with_tls((CERT_PEM, KEY_PEM))?
@@ -30,12 +28,32 @@ pub async fn run(
 
     info!("quic server start {addr:?}");
 
-    while let Some(mut conn) = server.accept().await {
+    loop {
+        let mut conn = tokio::select! {
+            conn = server.accept() => match conn {
+                Some(conn) => conn,
+                None => break,
+            },
+            _ = shutdown.wait() => break,
+        };
         info!("new connection from {}", conn.remote_addr()?);
         let sessions = sessions.clone();
         let topics = topics.clone();
+        let shutdown = shutdown.clone();
         tokio::spawn(async move {
-            while let Ok(Some(stream)) = conn.accept_bidirectional_stream().await {
+            // dropping conn would close its streams, it ends with the client
+            loop {
+                let stream = tokio::select! {
+                    stream = conn.accept_bidirectional_stream() => stream,
+                    // no new sessions, conn stays until the others have flushed
+                    _ = shutdown.wait() => {
+                        sessions.drained().await;
+                        break;
+                    }
+                };
+                let Ok(Some(stream)) = stream else {
+                    break;
+                };
                 info!(
                     "new bidirectional stream from id {}",
                     stream.connection().id()
@@ -43,7 +61,8 @@ pub async fn run(
                 let sessions = sessions.clone();
                 let topics = topics.clone();
                 tokio::spawn(async move {
-                    if let Err(e) = handle(stream, sessions, topics).await {
+                    let (reader, writer) = stream.split();
+                    if let Err(e) = serve_framed("quic", reader, writer, sessions, topics).await {
                         error!("handle error: {:?}", e);
                     };
                 });
@@ -51,59 +70,6 @@ pub async fn run(
         });
     }
 
-    Ok(())
-}
-
-async fn handle(
-    stream: BidirectionalStream,
-    sessions: Arc<SessionStore>,
-    topics: Arc<TopicStore>,
-) -> anyhow::Result<()> {
-    let (rx_stream, tx_stream) = stream.split();
-
-    let (client_tx, client_rx) = mpsc::channel(CHANNEL_SIZE);
-    let (server_tx, server_rx) = mpsc::channel(CHANNEL_SIZE);
-    let id = generate_uid();
-    info!("start grpc {id:?}");
-    let mut sess = Session::new(id.clone(), topics.clone(), server_tx);
-    sessions.add(sess.clone());
-    let mut tasks = Vec::with_capacity(3);
-    // session run
-    tasks.push(tokio::spawn(async move {
-        Ok::<(), anyhow::Error>(sess.run(client_rx).await?)
-    }));
-    // read loop
-    tasks.push(tokio::spawn(read_loop(rx_stream, client_tx)));
-    // write loop
-    tasks.push(tokio::spawn(write_loop(tx_stream, server_rx)));
-    // select all tasks
-    let result = futures::future::select_all(tasks).await.0?;
-    // leave info log
-    info!("{id:?} disconnected {result:?}");
-    sessions.remove(id);
-
-    result
-}
-
-async fn read_loop(
-    mut stream: ReceiveStream,
-    tx: mpsc::Sender<ClientMessage>,
-) -> anyhow::Result<()> {
-    while let Ok(Some(msg)) = stream.receive().await {
-        let msg: ClientMessage = msg.try_into()?;
-        info!("received {msg:?}");
-        tx.send(msg).await?;
-    }
-    Ok(())
-}
-
-async fn write_loop(
-    mut stream: SendStream,
-    mut rx: mpsc::Receiver<ServerMessage>,
-) -> anyhow::Result<()> {
-    while let Some(msg) = rx.recv().await {
-        info!("send {msg:?}");
-        stream.send(msg.try_into()?).await?;
-    }
+    info!("quic server stopped {addr:?}");
     Ok(())
 }
@@ -0,0 +1,149 @@
+// 优雅关闭：各传输停止 accept，所有 session 收到 going_away，在期限内等待待发消息发完
+
+use crate::SessionStore;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+// cloned into every transport, `trigger` stops them accepting new connections
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        Self {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    pub async fn wait(&self) {
+        let mut receiver = self.receiver.clone();
+        // the sender lives in self, wait_for can't fail
+        let _ = receiver.wait_for(|triggered| *triggered).await;
+    }
+
+    // false if some connections still had messages queued at the deadline
+    pub async fn drain(&self, sessions: &SessionStore, reason: &str, deadline: Duration) -> bool {
+        self.trigger();
+        sessions.go_away(reason);
+        match tokio::time::timeout(deadline, sessions.drained()).await {
+            Ok(()) => {
+                info!("all connections closed");
+                true
+            }
+            Err(_) => {
+                warn!("{} connections still open", sessions.connections());
+                false
+            }
+        }
+    }
+}
+
+// SIGINT or SIGTERM
+pub async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::test_utils::login_lines;
+    use crate::protocol::{run_tcp, serve_stream, Framing, Shutdown, TcpOptions};
+    use crate::session::{SessionStore, TopicStore};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, BufReader, DuplexStream, Lines, ReadHalf};
+
+    // a client logged in as bob over json lines
+    async fn connect(
+        sessions: &Arc<SessionStore>,
+        topics: &Arc<TopicStore>,
+    ) -> Lines<BufReader<ReadHalf<DuplexStream>>> {
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(serve_stream(
+            "tcp",
+            server,
+            Framing::Lines,
+            sessions.clone(),
+            topics.clone(),
+        ));
+        let (reader, mut writer) = tokio::io::split(client);
+        let mut lines = BufReader::new(reader).lines();
+        login_lines(&mut lines, &mut writer, "bob").await;
+        lines
+    }
+
+    #[tokio::test]
+    async fn graceful_shutdown() {
+        let sessions = Arc::new(SessionStore::new());
+        let topics = Arc::new(TopicStore::new());
+        let shutdown = Shutdown::new();
+        let listener = tokio::spawn(run_tcp(
+            "127.0.0.1:0".into(),
+            TcpOptions::default(),
+            sessions.clone(),
+            topics.clone(),
+            shutdown.clone(),
+        ));
+        let mut lines = connect(&sessions, &topics).await;
+        assert_eq!(sessions.connections(), 1);
+
+        let drain = shutdown.drain(&sessions, "bye", Duration::from_secs(5));
+        let (drained, reply) = tokio::join!(drain, lines.next_line());
+        let reply = reply.unwrap().unwrap();
+        assert!(reply.contains("\"going_away\""), "{reply}");
+        assert!(drained);
+        assert!(lines.next_line().await.unwrap().is_none());
+        assert_eq!(sessions.connections(), 0);
+        assert!(sessions.list().is_empty());
+
+        // the listener stopped accepting
+        listener.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn graceful_shutdown_resumable() {
+        let sessions = SessionStore::new().with_resume_grace(Duration::from_secs(60));
+        let sessions = Arc::new(sessions);
+        let topics = Arc::new(TopicStore::new());
+        let mut lines = connect(&sessions, &topics).await;
+
+        // not kept for resume, so nothing waits for the deadline
+        let shutdown = Shutdown::new();
+        let drain = shutdown.drain(&sessions, "bye", Duration::from_secs(5));
+        let drained = tokio::time::timeout(Duration::from_secs(1), drain).await;
+        assert_eq!(drained, Ok(true));
+        let reply = lines.next_line().await.unwrap().unwrap();
+        assert!(reply.contains("\"going_away\""), "{reply}");
+        assert!(sessions.list().is_empty());
+    }
+}
@@ -0,0 +1,211 @@
+// server-sent events + http post，给拦截 websocket 的代理
+// GET /sse 建立连接，第一个 `session` 事件带连接 id，之后每条 ServerMessage 一个事件
+// POST /sse/:id 提交 json ClientMessage
+
+use crate::protocol::{request_token, serve_connection, Connection, MessageSink, MessageStream};
+use crate::session::{SessionStore, TopicStore};
+use crate::utils::generate_uid;
+use crate::wire::{ClientMessage, ServerMessage};
+use axum::extract::{Path, Query};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use dashmap::DashMap;
+use futures::{sink, stream, StreamExt};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::error;
+
+const CHANNEL_SIZE: usize = 100;
+
+// key: connection id, value: the inbound side of its session
+#[derive(Default)]
+pub struct SseConnections {
+    connections: DashMap<String, Sender<ClientMessage>>,
+}
+
+// `/sse` and `/sse/:id`, layered with the session and topic stores like `/ws`
+pub fn sse_router() -> Router {
+    Router::new()
+        .route("/sse", get(sse_handler))
+        .route("/sse/:id", post(sse_post))
+        .layer(Extension(Arc::new(SseConnections::default())))
+}
+
+pub async fn sse_handler(
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+    Extension(connections): Extension<Arc<SseConnections>>,
+    Extension(sessions): Extension<Arc<SessionStore>>,
+    Extension(topics): Extension<Arc<TopicStore>>,
+) -> Response {
+    let user_name = match sessions.verify_connection(request_token(&headers, &params)) {
+        Ok(user_name) => user_name,
+        Err(e) => return (StatusCode::UNAUTHORIZED, e.to_string()).into_response(),
+    };
+
+    let id = generate_uid();
+    let (client_tx, client_rx) = channel(CHANNEL_SIZE);
+    let (server_tx, server_rx) = channel(CHANNEL_SIZE);
+    connections.connections.insert(id.clone(), client_tx);
+    let event_stream = server_tx.clone();
+    let conn = SseConnection {
+        inbound: ReceiverStream::new(client_rx),
+        outbound: server_tx,
+    };
+    let conn_id = id.clone();
+    tokio::spawn(async move {
+        let serve = serve_connection(conn, sessions, topics, user_name);
+        tokio::pin!(serve);
+        let result = tokio::select! {
+            result = &mut serve => result,
+            // the client left, a quiet session would not notice on its own
+            _ = event_stream.closed() => {
+                // ends the inbound stream and so the session
+                connections.connections.remove(&conn_id);
+                serve.await
+            }
+        };
+        if let Err(e) = result {
+            error!("sse error: {e:?}");
+        }
+        connections.connections.remove(&conn_id);
+    });
+
+    let events = ReceiverStream::new(server_rx).map(|msg: ServerMessage| {
+        let data = String::try_from(msg).unwrap_or_default();
+        Ok::<_, Infallible>(SseEvent::default().data(data))
+    });
+    let first = SseEvent::default().event("session").data(id);
+    let events = stream::once(async move { Ok(first) }).chain(events);
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+pub async fn sse_post(
+    Path(id): Path<String>,
+    Extension(connections): Extension<Arc<SseConnections>>,
+    Json(msg): Json<ClientMessage>,
+) -> StatusCode {
+    // no await while holding the map
+    let sender = match connections.connections.get(&id) {
+        Some(sender) => sender.clone(),
+        None => return StatusCode::NOT_FOUND,
+    };
+    match sender.send(msg).await {
+        Ok(()) => StatusCode::ACCEPTED,
+        Err(_) => StatusCode::NOT_FOUND,
+    }
+}
+
+struct SseConnection {
+    inbound: ReceiverStream<ClientMessage>,
+    outbound: Sender<ServerMessage>,
+}
+
+impl Connection for SseConnection {
+    fn protocol(&self) -> &'static str {
+        "sse"
+    }
+
+    fn split(self) -> (MessageStream, MessageSink) {
+        let inbound = self.inbound.map(Ok).boxed();
+        let outbound = sink::unfold(self.outbound, |tx, msg| async move {
+            tx.send(msg).await?;
+            Ok::<_, anyhow::Error>(tx)
+        });
+        (inbound, Box::pin(outbound))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::sse_router;
+    use crate::protocol::test_utils::{login_json, request, serve};
+    use crate::session::{SessionStore, TopicStore};
+    use axum::Extension;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    // read until `pattern` shows up in `buf`
+    async fn read_until(stream: &mut TcpStream, buf: &mut String, pattern: &str) {
+        while !buf.contains(pattern) {
+            let mut chunk = [0; 1024];
+            let n = stream.read(&mut chunk).await.unwrap();
+            assert!(n > 0, "closed before {pattern:?}: {buf}");
+            buf.push_str(&String::from_utf8_lossy(&chunk[..n]));
+        }
+    }
+
+    #[tokio::test]
+    async fn sse_session() {
+        let sessions = Arc::new(SessionStore::new());
+        let app = sse_router()
+            .layer(Extension(sessions.clone()))
+            .layer(Extension(Arc::new(TopicStore::new())));
+        let addr = serve(app);
+
+        let mut events = TcpStream::connect(addr).await.unwrap();
+        let get = format!("GET /sse HTTP/1.1\r\nhost: {addr}\r\n\r\n");
+        events.write_all(get.as_bytes()).await.unwrap();
+        let mut buf = String::new();
+        let session = "event:session\ndata:";
+        read_until(&mut events, &mut buf, session).await;
+        read_until(&mut events, &mut buf, "\n\n").await;
+        let start = buf.find(session).unwrap() + session.len();
+        let id = buf[start..].lines().next().unwrap().to_string();
+
+        let login = login_json("bob");
+        let (status, _) = request(addr, "POST", &format!("/sse/{id}"), "", &login).await;
+        assert_eq!(status, 202);
+        read_until(&mut events, &mut buf, "\"ack\"").await;
+        assert_eq!(sessions.find_by_user("bob").len(), 1);
+
+        let (status, _) = request(addr, "POST", "/sse/unknown", "", &login).await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn sse_client_leaves() {
+        let sessions = Arc::new(SessionStore::new());
+        let app = sse_router()
+            .layer(Extension(sessions.clone()))
+            .layer(Extension(Arc::new(TopicStore::new())));
+        let addr = serve(app);
+
+        let mut clients = vec![];
+        for i in 0..3 {
+            let mut events = TcpStream::connect(addr).await.unwrap();
+            let get = format!("GET /sse HTTP/1.1\r\nhost: {addr}\r\n\r\n");
+            events.write_all(get.as_bytes()).await.unwrap();
+            let mut buf = String::new();
+            let session = "event:session\ndata:";
+            read_until(&mut events, &mut buf, session).await;
+            read_until(&mut events, &mut buf, "\n\n").await;
+            let start = buf.find(session).unwrap() + session.len();
+            let id = buf[start..].lines().next().unwrap().to_string();
+            let login = login_json(&format!("bot{i}"));
+            request(addr, "POST", &format!("/sse/{id}"), "", &login).await;
+            read_until(&mut events, &mut buf, "\"ack\"").await;
+            clients.push(events);
+        }
+        drop(clients);
+
+        // the quiet sessions go away with their event streams
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while (0..3).any(|i| !sessions.find_by_user(&format!("bot{i}")).is_empty()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+}
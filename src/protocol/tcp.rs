@@ -0,0 +1,164 @@
+// 原始 tcp 传输，可选 tls（与 quic 相同的证书）
+
+use crate::protocol::{serve_stream, Framing, Shutdown, CERT_PEM, KEY_PEM};
+use crate::{SessionStore, TopicStore};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tracing::{error, info};
+
+// a client that never finishes the handshake is dropped after this
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// accept fails on e.g. too many open files, don't spin on it
+pub(crate) const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TcpOptions {
+    pub framing: Framing,
+    pub tls: bool,
+}
+
+pub async fn run_tcp(
+    addr: String,
+    options: TcpOptions,
+    sessions: Arc<SessionStore>,
+    topics: Arc<TopicStore>,
+    shutdown: Shutdown,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(&addr).await?;
+    let acceptor = match options.tls {
+        true => Some(tls_acceptor()?),
+        false => None,
+    };
+    info!("tcp server start {addr:?} {options:?}");
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.wait() => break,
+        };
+        let (stream, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("tcp accept error: {e:?}");
+                tokio::time::sleep(ACCEPT_RETRY).await;
+                continue;
+            }
+        };
+        info!("new tcp connection from {peer}");
+        let acceptor = acceptor.clone();
+        let sessions = sessions.clone();
+        let topics = topics.clone();
+        tokio::spawn(async move {
+            let result = match acceptor {
+                Some(acceptor) => {
+                    match accept_tls(&acceptor, stream, TLS_HANDSHAKE_TIMEOUT).await {
+                        Ok(stream) => {
+                            serve_stream("tls", stream, options.framing, sessions, topics).await
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                None => serve_stream("tcp", stream, options.framing, sessions, topics).await,
+            };
+            if let Err(e) = result {
+                error!("tcp {peer} error: {e:?}");
+            }
+        });
+    }
+    info!("tcp server stopped {addr:?}");
+    Ok(())
+}
+
+async fn accept_tls<S: AsyncRead + AsyncWrite + Unpin>(
+    acceptor: &TlsAcceptor,
+    stream: S,
+    timeout: Duration,
+) -> anyhow::Result<TlsStream<S>> {
+    match tokio::time::timeout(timeout, acceptor.accept(stream)).await {
+        Ok(stream) => Ok(stream?),
+        Err(_) => anyhow::bail!("tls handshake timed out"),
+    }
+}
+
+fn tls_acceptor() -> anyhow::Result<TlsAcceptor> {
+    let certs = CertificateDer::pem_slice_iter(CERT_PEM.as_bytes()).collect::<Result<_, _>>()?;
+    let key = PrivateKeyDer::from_pem_slice(KEY_PEM.as_bytes())?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::tcp::{accept_tls, tls_acceptor};
+    use crate::protocol::test_utils::login_lines;
+    use crate::protocol::{serve_stream, Framing};
+    use crate::session::{SessionStore, TopicStore};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    #[test]
+    fn load_certs() {
+        tls_acceptor().unwrap();
+    }
+
+    #[tokio::test]
+    async fn tls_handshake_timeout() {
+        // the client never sends its hello
+        let (_client, server) = tokio::io::duplex(1024);
+        let acceptor = tls_acceptor().unwrap();
+        let result = accept_tls(&acceptor, server, Duration::from_millis(50)).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn json_lines() {
+        let sessions = Arc::new(SessionStore::new());
+        let topics = Arc::new(TopicStore::new());
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(serve_stream(
+            "tcp",
+            server,
+            Framing::Lines,
+            sessions.clone(),
+            topics,
+        ));
+
+        let (reader, mut writer) = tokio::io::split(client);
+        let mut lines = BufReader::new(reader).lines();
+        let reply = login_lines(&mut lines, &mut writer, "bob").await;
+        assert!(reply.contains("\"request_id\":\"1\""), "{reply}");
+        assert_eq!(sessions.find_by_user("bob").len(), 1);
+
+        // not a ClientMessage, answered and the connection stays open
+        let invalid = r#"{"topic":5,"request_id":"2"}"#;
+        writer
+            .write_all(format!("hello\n{invalid}\n").as_bytes())
+            .await
+            .unwrap();
+        let reply = lines.next_line().await.unwrap().unwrap();
+        assert!(reply.contains("\"code\":4"), "{reply}");
+        assert!(!reply.contains("request_id"), "{reply}");
+        let reply = lines.next_line().await.unwrap().unwrap();
+        assert!(reply.contains("\"request_id\":\"2\""), "{reply}");
+        assert!(reply.contains("\"error\""), "{reply}");
+
+        let join = r#"{"topic":"room","message":{"create_room":{}},"request_id":"3"}"#;
+        writer
+            .write_all(format!("{join}\n").as_bytes())
+            .await
+            .unwrap();
+        let reply = lines.next_line().await.unwrap().unwrap();
+        assert!(reply.contains("\"request_id\":\"3\""), "{reply}");
+        assert!(reply.contains("\"ack\""), "{reply}");
+    }
+}
@@ -0,0 +1,54 @@
+// 测试共用的客户端
+
+use axum::Router;
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Lines};
+use tokio::net::TcpStream;
+
+// a json Login of `name` with request_id 1
+pub(crate) fn login_json(name: &str) -> String {
+    format!(r#"{{"topic":"","message":{{"login":{{"name":"{name}"}}}},"request_id":"1"}}"#)
+}
+
+// log in over json lines, returns the reply once it is known to be an ack
+pub(crate) async fn login_lines<R, W>(lines: &mut Lines<R>, writer: &mut W, name: &str) -> String
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let login = format!("{}\n", login_json(name));
+    writer.write_all(login.as_bytes()).await.unwrap();
+    let reply = lines.next_line().await.unwrap().unwrap();
+    assert!(reply.contains("\"ack\""), "{reply}");
+    reply
+}
+
+// serve `app` on a free local port
+pub(crate) fn serve(app: Router) -> SocketAddr {
+    let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+// one http request per connection, `headers` end with \r\n, returns the status and the body
+pub(crate) async fn request(
+    addr: SocketAddr,
+    method: &str,
+    path: &str,
+    headers: &str,
+    body: &str,
+) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "{method} {path} HTTP/1.1\r\nhost: {addr}\r\ncontent-type: application/json\r\n\
+         {headers}content-length: {}\r\nconnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+    (status, body)
+}
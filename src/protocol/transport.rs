@@ -0,0 +1,338 @@
+// 传输抽象，所有协议共用同一个 session 生命周期
+
+use crate::wire::client_message::Message;
+use crate::wire::{ClientMessage, Event, InvalidFrame, ServerMessage};
+use crate::{generate_uid, ChatError, Session, SessionStore, TopicStore};
+use futures::stream::BoxStream;
+use futures::{future, stream, Sink, SinkExt, StreamExt};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::mpsc::channel;
+use tracing::{info, trace};
+
+const CHANNEL_SIZE: usize = 100;
+
+pub type MessageStream = BoxStream<'static, anyhow::Result<ClientMessage>>;
+pub type MessageSink = Pin<Box<dyn Sink<ServerMessage, Error = anyhow::Error> + Send>>;
+
+// one client connection of a transport, ClientMessage in and ServerMessage out
+pub trait Connection: Send + 'static {
+    // transport name for logs
+    fn protocol(&self) -> &'static str;
+
+    fn split(self) -> (MessageStream, MessageSink);
+}
+
+// run a session over `conn` until either side ends, `user_name` is set when the
+// transport already authenticated the connection
+pub async fn serve_connection<C: Connection>(
+    conn: C,
+    sessions: Arc<SessionStore>,
+    topics: Arc<TopicStore>,
+    user_name: Option<String>,
+) -> anyhow::Result<()> {
+    let protocol = conn.protocol();
+    let (mut inbound, mut outbound) = conn.split();
+
+    let (client_tx, client_rx) = channel(CHANNEL_SIZE);
+    let (server_tx, mut server_rx) = channel(CHANNEL_SIZE);
+
+    let id = generate_uid();
+    let invalid_tx = server_tx.clone();
+    let mut sess = Session::new(id.clone(), sessions.clone(), topics, server_tx);
+    if let Err(e) = sessions.add_with_user(sess.clone(), user_name) {
+        outbound
+            .send(ServerMessage::event("", Event::Error((&e).into())))
+            .await?;
+        outbound.close().await?;
+        return Err(e);
+    }
+    info!("start {protocol} {id:?}");
+
+    let mut tasks = Vec::with_capacity(3);
+    // session run
+    tasks.push(tokio::spawn(async move { sess.run(client_rx).await }));
+    // read loop
+    let read_id = id.clone();
+    let read = tokio::spawn(async move {
+        while let Some(msg) = inbound.next().await {
+            let msg = match invalid_frame(msg)? {
+                Ok(msg) => msg,
+                Err(reply) => {
+                    invalid_tx.send(reply).await?;
+                    continue;
+                }
+            };
+            // no payload, logins carry passwords and tokens
+            trace!(
+                "{protocol} {read_id:?} received request {:?}",
+                msg.request_id
+            );
+            client_tx.send(msg).await?;
+        }
+        Ok(())
+    });
+    let stop_reading = read.abort_handle();
+    tasks.push(read);
+    // write loop, open until every queued message is sent
+    let guard = sessions.open_connection();
+    let write_id = id.clone();
+    tasks.push(tokio::spawn(async move {
+        let _guard = guard;
+        while let Some(msg) = server_rx.recv().await {
+            // no payload, acks carry tokens
+            trace!("{protocol} {write_id:?} send {:?}", msg.request_id);
+            outbound.send(msg).await?;
+        }
+        Ok(())
+    }));
+    // select all tasks
+    let result = future::select_all(tasks).await.0;
+    // a closed session must not wait for the client to hang up
+    stop_reading.abort();
+    info!("{protocol} {id:?} disconnected {result:?}");
+    sessions.release(id);
+
+    // a panicked task is still cleaned up above
+    result?
+}
+
+// a frame that does not decode becomes the error reply to send, other errors end the connection
+fn invalid_frame(
+    msg: anyhow::Result<ClientMessage>,
+) -> anyhow::Result<Result<ClientMessage, ServerMessage>> {
+    let invalid = match msg {
+        Ok(msg) => return Ok(Ok(msg)),
+        Err(e) => e.downcast::<InvalidFrame>()?,
+    };
+    let e = anyhow::Error::from(ChatError::InvalidMessage(invalid.reason));
+    let mut reply = ServerMessage::event("", Event::Error((&e).into()));
+    reply.request_id = invalid.request_id;
+    Ok(Err(reply))
+}
+
+// a connection already split by its transport
+pub struct SplitConnection {
+    protocol: &'static str,
+    inbound: MessageStream,
+    outbound: MessageSink,
+}
+
+impl Connection for SplitConnection {
+    fn protocol(&self) -> &'static str {
+        self.protocol
+    }
+
+    fn split(self) -> (MessageStream, MessageSink) {
+        (self.inbound, self.outbound)
+    }
+}
+
+// for transports without handshake headers: a first login frame carrying a token
+// authenticates the connection, and is refused when tokens are required, a first
+// resume frame is checked by the session against its session token
+pub async fn serve_with_login<C: Connection>(
+    conn: C,
+    sessions: Arc<SessionStore>,
+    topics: Arc<TopicStore>,
+) -> anyhow::Result<()> {
+    let protocol = conn.protocol();
+    let (mut inbound, mut outbound) = conn.split();
+    let first = loop {
+        match inbound.next().await {
+            Some(msg) => match invalid_frame(msg)? {
+                Ok(msg) => break msg,
+                Err(reply) => outbound.send(reply).await?,
+            },
+            None => return Ok(()),
+        }
+    };
+    let token = match &first.message {
+        Some(Message::Login(login)) if !login.token.is_empty() => Some(login.token.as_str()),
+        _ => None,
+    };
+    // the login itself goes on to the session, which verifies the token again
+    let verified = match &first.message {
+        Some(Message::Resume(_)) => Ok(None),
+        _ => sessions.verify_connection(token),
+    };
+    if let Err(e) = verified {
+        let mut reply = ServerMessage::event(&first.topic, Event::Error((&e).into()));
+        reply.request_id = first.request_id;
+        outbound.send(reply).await?;
+        outbound.close().await?;
+        return Err(e);
+    }
+    let conn = SplitConnection {
+        protocol,
+        inbound: stream::once(future::ready(Ok(first)))
+            .chain(inbound)
+            .boxed(),
+        outbound,
+    };
+    serve_connection(conn, sessions, topics, None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::{
+        serve_connection, serve_with_login, Connection, MessageSink, MessageStream,
+    };
+    use crate::session::{SessionStore, TopicStore};
+    use crate::wire::client_message::Message;
+    use crate::wire::{ClientMessage, Event, Login, Resume, ServerMessage};
+    use crate::{AnyNameAuthenticator, DuplicateLogin, TokenSigner};
+    use futures::{sink, StreamExt};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::mpsc::{channel, Receiver, Sender};
+    use tokio_stream::wrappers::ReceiverStream;
+
+    // in-memory transport
+    struct Pipe {
+        inbound: Receiver<ClientMessage>,
+        outbound: Sender<ServerMessage>,
+    }
+
+    impl Connection for Pipe {
+        fn protocol(&self) -> &'static str {
+            "pipe"
+        }
+
+        fn split(self) -> (MessageStream, MessageSink) {
+            let inbound = ReceiverStream::new(self.inbound).map(Ok).boxed();
+            let outbound = sink::unfold(self.outbound, |tx, msg| async move {
+                tx.send(msg).await?;
+                Ok::<_, anyhow::Error>(tx)
+            });
+            (inbound, Box::pin(outbound))
+        }
+    }
+
+    fn pipe() -> (Pipe, Sender<ClientMessage>, Receiver<ServerMessage>) {
+        let (client_tx, client_rx) = channel(4);
+        let (server_tx, server_rx) = channel(4);
+        let conn = Pipe {
+            inbound: client_rx,
+            outbound: server_tx,
+        };
+        (conn, client_tx, server_rx)
+    }
+
+    #[tokio::test]
+    async fn connection_lifecycle() {
+        let sessions = Arc::new(SessionStore::new());
+        let topics = Arc::new(TopicStore::new());
+        let (conn, client_tx, mut server_rx) = pipe();
+        let task = tokio::spawn(serve_connection(conn, sessions.clone(), topics, None));
+
+        let login = ClientMessage {
+            message: Some(Message::Login(Login {
+                name: "bob".into(),
+                ..Default::default()
+            })),
+            request_id: "1".into(),
+            ..Default::default()
+        };
+        client_tx.send(login).await.unwrap();
+        let reply = server_rx.recv().await.unwrap();
+        assert!(matches!(reply.event, Some(Event::Ack(_))));
+        assert_eq!(sessions.find_by_user("bob").len(), 1);
+
+        // the client going away removes the session
+        drop(client_tx);
+        task.await.unwrap().unwrap();
+        assert!(sessions.find_by_user("bob").is_empty());
+    }
+
+    #[tokio::test]
+    async fn connection_rejected() {
+        let sessions = Arc::new(SessionStore::with_auth(
+            Arc::new(AnyNameAuthenticator),
+            DuplicateLogin::Reject,
+        ));
+        let topics = Arc::new(TopicStore::new());
+        let (first, _first_tx, _first_rx) = pipe();
+        tokio::spawn(serve_connection(
+            first,
+            sessions.clone(),
+            topics.clone(),
+            Some("bob".into()),
+        ));
+        while sessions.find_by_user("bob").is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        // the transport authenticated bob again, the client gets the error before the close
+        let (second, _second_tx, mut second_rx) = pipe();
+        let result = serve_connection(second, sessions.clone(), topics, Some("bob".into())).await;
+        assert!(result.is_err());
+        let reply = second_rx.recv().await.unwrap();
+        assert!(matches!(reply.event, Some(Event::Error(_))));
+        assert_eq!(sessions.find_by_user("bob").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn resume_first_frame() {
+        let signer = Arc::new(TokenSigner::new("secret", Duration::from_secs(60)));
+        let sessions = SessionStore::new()
+            .with_token_signer(signer.clone(), true)
+            .with_resume_grace(Duration::from_secs(60));
+        let sessions = Arc::new(sessions);
+        let topics = Arc::new(TopicStore::new());
+        let command = |message| ClientMessage {
+            message: Some(message),
+            request_id: "1".into(),
+            ..Default::default()
+        };
+        let resume = |session_token: &str| {
+            command(Message::Resume(Resume {
+                session_token: session_token.into(),
+                ..Default::default()
+            }))
+        };
+
+        let (conn, client_tx, mut server_rx) = pipe();
+        let task = tokio::spawn(serve_with_login(conn, sessions.clone(), topics.clone()));
+        let login = Login {
+            token: signer.issue("bob"),
+            ..Default::default()
+        };
+        client_tx
+            .send(command(Message::Login(login)))
+            .await
+            .unwrap();
+        let session_token = match server_rx.recv().await.unwrap().event {
+            Some(Event::Ack(ack)) => ack.session_token,
+            event => panic!("unexpected {event:?}"),
+        };
+        drop(client_tx);
+        task.await.unwrap().unwrap();
+
+        // no token when tokens are required, the session token is enough
+        let (conn, client_tx, mut server_rx) = pipe();
+        tokio::spawn(serve_with_login(conn, sessions.clone(), topics.clone()));
+        client_tx.send(resume(&session_token)).await.unwrap();
+        let reply = server_rx.recv().await.unwrap();
+        assert!(matches!(reply.event, Some(Event::Ack(_))), "{reply:?}");
+        assert_eq!(sessions.find_by_user("bob").len(), 1);
+
+        // a failed resume does not open the way to a login without a token
+        let (conn, client_tx, mut server_rx) = pipe();
+        tokio::spawn(serve_with_login(conn, sessions.clone(), topics));
+        client_tx.send(resume("unknown")).await.unwrap();
+        let reply = server_rx.recv().await.unwrap();
+        assert!(matches!(reply.event, Some(Event::Error(_))));
+        let login = Login {
+            name: "eve".into(),
+            ..Default::default()
+        };
+        client_tx
+            .send(command(Message::Login(login)))
+            .await
+            .unwrap();
+        let reply = server_rx.recv().await.unwrap();
+        assert!(matches!(reply.event, Some(Event::Error(_))));
+        assert!(sessions.find_by_user("eve").is_empty());
+    }
+}
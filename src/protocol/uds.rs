@@ -0,0 +1,152 @@
+// unix domain socket 传输，给同机的 sidecar 使用
+
+use crate::generate_uid;
+use crate::protocol::tcp::ACCEPT_RETRY;
+use crate::protocol::{serve_stream, Framing, Shutdown};
+use crate::{SessionStore, TopicStore};
+use std::fs::DirBuilder;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::UnixListener;
+use tracing::{error, info};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UdsOptions {
+    pub framing: Framing,
+    // permission bits of the socket file, e.g. 0o660, umask applies without it
+    pub mode: Option<u32>,
+}
+
+pub async fn run_uds(
+    path: String,
+    options: UdsOptions,
+    sessions: Arc<SessionStore>,
+    topics: Arc<TopicStore>,
+    shutdown: Shutdown,
+) -> anyhow::Result<()> {
+    // a socket left behind by an earlier run, anything else is not ours to remove
+    match std::fs::symlink_metadata(&path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(&path)?,
+        Ok(_) => anyhow::bail!("{path:?} exists and is not a socket"),
+        Err(_) => {}
+    }
+    let listener = match options.mode {
+        Some(mode) => bind_with_mode(Path::new(&path), mode)?,
+        None => UnixListener::bind(&path)?,
+    };
+    info!("uds server start {path:?} {options:?}");
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.wait() => break,
+        };
+        let stream = match accepted {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                error!("uds accept error: {e:?}");
+                tokio::time::sleep(ACCEPT_RETRY).await;
+                continue;
+            }
+        };
+        info!("new uds connection on {path:?}");
+        let sessions = sessions.clone();
+        let topics = topics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_stream("uds", stream, options.framing, sessions, topics).await {
+                error!("uds error: {e:?}");
+            }
+        });
+    }
+    // nobody can connect anymore
+    std::fs::remove_file(&path)?;
+    info!("uds server stopped {path:?}");
+    Ok(())
+}
+
+// bound in a private directory and moved into place once `mode` is set,
+// so nobody can connect while the socket still has the umask permissions
+fn bind_with_mode(path: &Path, mode: u32) -> anyhow::Result<UnixListener> {
+    let dir = path.with_file_name(format!(".chat-{}", generate_uid()));
+    DirBuilder::new().mode(0o700).create(&dir)?;
+    let bound = (|| {
+        let tmp = dir.join("sock");
+        let listener = UnixListener::bind(&tmp)?;
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(mode))?;
+        std::fs::rename(&tmp, path)?;
+        Ok(listener)
+    })();
+    std::fs::remove_dir_all(&dir)?;
+    bound
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_uid;
+    use crate::protocol::test_utils::login_lines;
+    use crate::protocol::{run_uds, Framing, Shutdown, UdsOptions};
+    use crate::session::{SessionStore, TopicStore};
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::UnixStream;
+
+    #[tokio::test]
+    async fn uds_lines() {
+        let path = std::env::temp_dir().join(format!("chat-{}.sock", generate_uid()));
+        let path = path.to_str().unwrap().to_string();
+        // a stale socket is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let options = UdsOptions {
+            framing: Framing::Lines,
+            mode: Some(0o600),
+        };
+        let sessions = Arc::new(SessionStore::new());
+        let shutdown = Shutdown::new();
+        let server = tokio::spawn(run_uds(
+            path.clone(),
+            options,
+            sessions.clone(),
+            Arc::new(TopicStore::new()),
+            shutdown.clone(),
+        ));
+
+        let stream = loop {
+            match UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::task::yield_now().await,
+            }
+        };
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        login_lines(&mut lines, &mut writer, "bot").await;
+        assert_eq!(sessions.find_by_user("bot").len(), 1);
+
+        // stops accepting and removes the socket file
+        shutdown.trigger();
+        server.await.unwrap().unwrap();
+        assert!(std::fs::metadata(&path).is_err());
+    }
+
+    #[tokio::test]
+    async fn uds_not_a_socket() {
+        let path = std::env::temp_dir().join(format!("chat-{}.sock", generate_uid()));
+        let path = path.to_str().unwrap().to_string();
+        std::fs::write(&path, "data").unwrap();
+        let result = run_uds(
+            path.clone(),
+            UdsOptions::default(),
+            Arc::new(SessionStore::new()),
+            Arc::new(TopicStore::new()),
+            Shutdown::new(),
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,83 +1,152 @@
-use crate::session::{Session, SessionStore, TopicStore};
-use crate::utils::generate_uid;
-use crate::wire::ClientMessage;
+use crate::auth::bearer_token;
+use crate::protocol::{serve_connection, Connection, MessageSink, MessageStream};
+use crate::session::{SessionStore, TopicStore};
+use crate::wire::{ClientMessage, Encoding, ServerMessage};
 use axum::extract::ws::{Message, WebSocket};
-use axum::extract::WebSocketUpgrade;
-use axum::response::IntoResponse;
+use axum::extract::{Query, WebSocketUpgrade};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum::Extension;
-use futures::{future, SinkExt, StreamExt};
+use futures::{future, SinkExt, StreamExt, TryStreamExt};
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::mpsc::channel;
-use tracing::info;
-
-const CHANNEL_SIZE: usize = 100;
+use tracing::{error, info};
 
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
     Extension(sessions): Extension<Arc<SessionStore>>,
     Extension(topics): Extension<Arc<TopicStore>>,
-) -> impl IntoResponse {
-    ws.on_upgrade(|s| async { handle_ws(s, sessions, topics).await.unwrap() })
+) -> Response {
+    let user_name = match sessions.verify_connection(request_token(&headers, &params)) {
+        Ok(user_name) => user_name,
+        Err(e) => return (StatusCode::UNAUTHORIZED, e.to_string()).into_response(),
+    };
+    // `Sec-WebSocket-Protocol: protobuf` or `json`, json text frames without one
+    ws.protocols([Encoding::Protobuf.name(), Encoding::Json.name()])
+        .on_upgrade(|s| async {
+            if let Err(e) = handle_ws(s, sessions, topics, user_name).await {
+                error!("ws error: {e:?}");
+            }
+        })
+        .into_response()
+}
+
+// `Authorization: Bearer <token>` or `?token=<token>` for browsers
+pub(crate) fn request_token<'a>(
+    headers: &'a HeaderMap,
+    params: &'a HashMap<String, String>,
+) -> Option<&'a str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(bearer_token)
+        .or_else(|| params.get("token").map(String::as_str))
 }
 
 pub async fn handle_ws(
     stream: WebSocket,
     sessions: Arc<SessionStore>,
     topics: Arc<TopicStore>,
+    user_name: Option<String>,
 ) -> anyhow::Result<()> {
-    // info!("{stream:?}");
-
-    let (tx, rx) = channel(CHANNEL_SIZE);
-    let (tx1, mut rx1) = channel(CHANNEL_SIZE);
-
-    let id = generate_uid();
-    let mut sess = Session::new(id.clone(), topics.clone(), tx1.clone());
-
-    sessions.add(sess.clone());
-    let mut tasks = vec![];
-    let sess_task = tokio::spawn(async move { Ok::<(), anyhow::Error>(sess.run(rx).await?) });
-    tasks.push(sess_task);
-
-    let (mut sender, mut reciver) = stream.split();
+    let encoding = stream
+        .protocol()
+        .and_then(|protocol| protocol.to_str().ok())
+        .and_then(Encoding::from_name)
+        .unwrap_or_default();
+    info!("ws encoding {}", encoding.name());
+    let conn = WsConnection { stream, encoding };
+    serve_connection(conn, sessions, topics, user_name).await
+}
 
-    let send_task = tokio::spawn(async move {
-        while let Some(Ok(msg)) = reciver.next().await {
-            match msg {
-                Message::Text(msg) => {
-                    info!("recive message {msg:?}");
-                    let msg: ClientMessage = msg.try_into()?;
-                    // send to session handler
-                    tx.send(msg).await?;
-                }
-                Message::Close(e) => {
-                    info!("Close: {e:?}");
-                    return Ok(());
-                }
-                _ => {}
-            }
-        }
-        Ok::<(), anyhow::Error>(())
-    });
-    tasks.push(send_task);
+struct WsConnection {
+    stream: WebSocket,
+    // of the frames we send, clients may send either
+    encoding: Encoding,
+}
 
-    let recv_task = tokio::spawn(async move {
-        while let Some(msg) = rx1.recv().await {
-            sender.send(Message::Text(msg.try_into()?)).await?;
-        }
-        Ok::<(), anyhow::Error>(())
-    });
-    tasks.push(recv_task);
+impl Connection for WsConnection {
+    fn protocol(&self) -> &'static str {
+        "ws"
+    }
 
-    // multi task select all
-    let result = future::select_all(tasks).await.0;
-    info!("{id:?} disconnected");
-    sessions.remove(id);
+    fn split(self) -> (MessageStream, MessageSink) {
+        let encoding = self.encoding;
+        let (sender, receiver) = self.stream.split();
+        let inbound = receiver
+            .map_err(anyhow::Error::from)
+            .try_take_while(|msg| future::ready(Ok(!matches!(msg, Message::Close(_)))))
+            .try_filter_map(|msg| {
+                future::ready(match msg {
+                    Message::Text(msg) => ClientMessage::try_from(msg).map(Some),
+                    Message::Binary(msg) => Encoding::Protobuf.decode_client(&msg).map(Some),
+                    _ => Ok(None),
+                })
+            })
+            .boxed();
+        let outbound = sender
+            .sink_map_err(anyhow::Error::from)
+            .with(move |msg| future::ready(ws_message(encoding, msg)));
+        (inbound, Box::pin(outbound))
+    }
+}
 
-    result?
+fn ws_message(encoding: Encoding, msg: ServerMessage) -> anyhow::Result<Message> {
+    Ok(match encoding {
+        Encoding::Json => Message::Text(msg.try_into()?),
+        Encoding::Protobuf => Message::Binary(encoding.encode(&msg)?.to_vec()),
+    })
 }
 
 #[cfg(test)]
 mod test {
+    use crate::protocol::test_utils::serve;
+    use crate::protocol::ws_handler;
+    use crate::session::{SessionStore, TopicStore};
+    use crate::wire::client_message::Message;
+    use crate::wire::{ClientMessage, Encoding, Event, Login, ServerMessage};
+    use axum::routing::get;
+    use axum::{Extension, Router};
+    use futures::{SinkExt, StreamExt};
+    use std::sync::Arc;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    #[tokio::test]
+    async fn protobuf_subprotocol() {
+        let app = Router::new()
+            .route("/ws", get(ws_handler))
+            .layer(Extension(Arc::new(SessionStore::new())))
+            .layer(Extension(Arc::new(TopicStore::new())));
+        let addr = serve(app);
+
+        let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("sec-websocket-protocol", "protobuf, json".parse().unwrap());
+        let (mut socket, response) = tokio_tungstenite::connect_async(request).await.unwrap();
+        assert_eq!(response.headers()["sec-websocket-protocol"], "protobuf");
+
+        let login = ClientMessage {
+            message: Some(Message::Login(Login {
+                name: "bob".into(),
+                ..Default::default()
+            })),
+            request_id: "1".into(),
+            ..Default::default()
+        };
+        let buf = Encoding::Protobuf.encode(&login).unwrap();
+        socket.send(WsMessage::Binary(buf.to_vec())).await.unwrap();
+        let reply = match socket.next().await.unwrap().unwrap() {
+            WsMessage::Binary(buf) => Encoding::Protobuf.decode::<ServerMessage>(&buf).unwrap(),
+            msg => panic!("unexpected {msg:?}"),
+        };
+        assert_eq!(reply.request_id, "1");
+        assert!(matches!(reply.event, Some(Event::Ack(_))));
+    }
 
     // 测试多 task 结束
     #[tokio::test]
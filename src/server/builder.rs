@@ -0,0 +1,396 @@
+// 组装整个服务：store、axum router、grpc、quic、tcp、uds 和管理接口
+// 给把聊天嵌进更大服务的使用方，examples/server 只是读配置再调用它
+
+use crate::auth::{Authenticator, TokenSigner};
+use crate::protocol::{self, Shutdown, TcpOptions};
+use crate::server::{ServerConfig, StoreConfig};
+use crate::session::{ChatHooks, SessionStore, TopicStore};
+use crate::storage::{FileStore, MessageStore};
+use crate::wire::chat_admin_server::ChatAdminServer;
+use crate::wire::chat_service_server::ChatServiceServer;
+use axum::http::StatusCode;
+use axum::routing::{get, get_service};
+use axum::{Extension, Router};
+use futures::future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tower_http::services::ServeDir;
+use tracing::{info, warn};
+
+pub struct ChatServerBuilder {
+    config: ServerConfig,
+    sessions: Option<Arc<SessionStore>>,
+    topics: Option<Arc<TopicStore>>,
+    authenticator: Option<Arc<dyn Authenticator>>,
+    message_store: Option<Arc<dyn MessageStore>>,
+    hooks: Option<Arc<dyn ChatHooks>>,
+    router: Option<Router>,
+}
+
+impl ChatServerBuilder {
+    pub fn new(config: ServerConfig) -> Self {
+        Self {
+            config,
+            sessions: None,
+            topics: None,
+            authenticator: None,
+            message_store: None,
+            hooks: None,
+            router: None,
+        }
+    }
+
+    // used as is, `auth_config`, `resume_grace_secs` and `hooks` are ignored
+    pub fn session_store(mut self, sessions: Arc<SessionStore>) -> Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
+    // used as is, `topic_config` and `store_config` are ignored
+    pub fn topic_store(mut self, topics: Arc<TopicStore>) -> Self {
+        self.topics = Some(topics);
+        self
+    }
+
+    // replaces `auth_config.authenticator`
+    pub fn authenticator(mut self, authenticator: Arc<dyn Authenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    // replaces `store_config`
+    pub fn message_store(mut self, store: Arc<dyn MessageStore>) -> Self {
+        self.message_store = Some(store);
+        self
+    }
+
+    // called by the sessions of every transport
+    pub fn hooks(mut self, hooks: Arc<dyn ChatHooks>) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    // extra routes served on the ws address, with the stores as extensions like `/ws`
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = Some(router);
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<ChatServerHandle> {
+        let config = self.config;
+        let sessions = match self.sessions {
+            Some(sessions) => sessions,
+            None => {
+                let auth = &config.auth_config;
+                let authenticator = match self.authenticator {
+                    Some(authenticator) => authenticator,
+                    None => auth.authenticator.authenticator()?,
+                };
+                let mut store =
+                    SessionStore::with_auth(authenticator, auth.duplicate_login.policy());
+                if let Some(token) = &auth.token {
+                    let ttl = Duration::from_secs(token.ttl_secs);
+                    let signer = TokenSigner::new(token.secret.clone(), ttl);
+                    store = store.with_token_signer(Arc::new(signer), token.required);
+                }
+                if let Some(hooks) = self.hooks {
+                    store = store.with_hooks(hooks);
+                }
+                let grace = Duration::from_secs(config.session_config.resume_grace_secs);
+                Arc::new(store.with_resume_grace(grace))
+            }
+        };
+        let topics = match self.topics {
+            Some(topics) => topics,
+            None => {
+                let options = config.topic_config.options();
+                let store = match (self.message_store, &config.store_config) {
+                    (Some(store), _) => Some(store),
+                    (None, StoreConfig::Memory) => None,
+                    (None, StoreConfig::File { dir }) => {
+                        let store = FileStore::open(dir, options.history_size)?;
+                        Some(Arc::new(store) as Arc<dyn MessageStore>)
+                    }
+                };
+                Arc::new(match store {
+                    Some(store) => TopicStore::with_store(options, store)?,
+                    None => TopicStore::with_options(options),
+                })
+            }
+        };
+        Ok(ChatServerHandle {
+            config: Arc::new(config),
+            sessions,
+            topics,
+            router: self.router,
+            shutdown: Shutdown::new(),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct ChatServerHandle {
+    config: Arc<ServerConfig>,
+    sessions: Arc<SessionStore>,
+    topics: Arc<TopicStore>,
+    router: Option<Router>,
+    shutdown: Shutdown,
+}
+
+impl ChatServerHandle {
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    pub fn sessions(&self) -> &Arc<SessionStore> {
+        &self.sessions
+    }
+
+    pub fn topics(&self) -> &Arc<TopicStore> {
+        &self.topics
+    }
+
+    // makes `run` stop accepting, send going_away to every session and return
+    pub fn shutdown(&self) {
+        self.shutdown.trigger();
+    }
+
+    // serves every configured transport until `shutdown` or until one of them fails
+    pub async fn run(self) -> anyhow::Result<()> {
+        let mut servers = Vec::new();
+        if let Err(e) = self.start(&mut servers) {
+            // stop the ones already started
+            self.shutdown.trigger();
+            return Err(e);
+        }
+
+        // a transport only stops by itself on error, e.g. its address is taken
+        let (result, stopped) = tokio::select! {
+            _ = self.shutdown.wait() => (Ok(()), None),
+            (result, index, _) = future::select_all(servers.iter_mut()), if !servers.is_empty() => {
+                (result.map_err(anyhow::Error::from).and_then(|result| result), Some(index))
+            }
+        };
+        if let Some(index) = stopped {
+            servers.remove(index);
+        }
+        let deadline = Duration::from_secs(self.config.session_config.shutdown_deadline_secs);
+        let stop_by = Instant::now() + deadline;
+        self.shutdown
+            .drain(&self.sessions, "server shutting down", deadline)
+            .await;
+        // the transports stop on the trigger, e.g. uds removes its socket file
+        if tokio::time::timeout_at(stop_by, future::join_all(servers))
+            .await
+            .is_err()
+        {
+            warn!("transports still running at the shutdown deadline");
+        }
+        result
+    }
+
+    fn start(&self, servers: &mut Vec<JoinHandle<anyhow::Result<()>>>) -> anyhow::Result<()> {
+        let config = &self.config;
+
+        if let Some(ws) = &config.ws_config {
+            let addr: SocketAddr = ws.addr.parse()?;
+            let mut router = Router::new()
+                .route("/ws", get(protocol::ws_handler))
+                .merge(protocol::sse_router());
+            if let Some(extra) = self.router.clone() {
+                router = router.merge(extra);
+            }
+            let mut router = router
+                .layer(Extension(self.sessions.clone()))
+                .layer(Extension(self.topics.clone()));
+            if let Some(static_dir) = &ws.static_dir {
+                let files = ServeDir::new(static_dir).append_index_html_on_directories(true);
+                router = router.fallback(get_service(files).handle_error(
+                    |error: std::io::Error| async move {
+                        (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!("Unhandled internal error: {}", error),
+                        )
+                    },
+                ));
+            }
+            servers.push(self.serve_http("ws", addr, router)?);
+        }
+
+        if let Some(grpc) = &config.grpc_config {
+            let addr: SocketAddr = grpc.addr.parse()?;
+            let service = protocol::ChatServer::new(self.sessions.clone(), self.topics.clone());
+            let shutdown = self.shutdown.clone();
+            info!("grpc server start {addr}");
+            servers.push(tokio::spawn(async move {
+                tonic::transport::Server::builder()
+                    .add_service(ChatServiceServer::new(service))
+                    .serve_with_shutdown(addr, shutdown.wait())
+                    .await?;
+                Ok(())
+            }));
+        }
+
+        if let Some(quic) = &config.quic_config {
+            servers.push(tokio::spawn(protocol::run(
+                quic.addr.clone(),
+                self.sessions.clone(),
+                self.topics.clone(),
+                self.shutdown.clone(),
+            )));
+        }
+
+        if let Some(admin) = &config.admin_config {
+            let addr: SocketAddr = admin.addr.parse()?;
+            let router = protocol::with_admin_token(protocol::admin_router(), admin.token.clone())
+                .layer(Extension(self.sessions.clone()))
+                .layer(Extension(self.topics.clone()));
+            servers.push(self.serve_http("admin", addr, router)?);
+            if let Some(grpc_addr) = &admin.grpc_addr {
+                let addr: SocketAddr = grpc_addr.parse()?;
+                let service = ChatAdminServer::with_interceptor(
+                    protocol::AdminServer::new(self.sessions.clone(), self.topics.clone()),
+                    protocol::admin_interceptor(admin.token.clone()),
+                );
+                let shutdown = self.shutdown.clone();
+                info!("grpc admin server start {addr}");
+                servers.push(tokio::spawn(async move {
+                    tonic::transport::Server::builder()
+                        .add_service(service)
+                        .serve_with_shutdown(addr, shutdown.wait())
+                        .await?;
+                    Ok(())
+                }));
+            }
+        }
+
+        if let Some(tcp) = &config.tcp_config {
+            let options = TcpOptions {
+                framing: tcp.framing.framing(),
+                tls: tcp.tls,
+            };
+            servers.push(tokio::spawn(protocol::run_tcp(
+                tcp.addr.clone(),
+                options,
+                self.sessions.clone(),
+                self.topics.clone(),
+                self.shutdown.clone(),
+            )));
+        }
+
+        if let Some(uds) = &config.uds_config {
+            #[cfg(unix)]
+            {
+                let options = protocol::UdsOptions {
+                    framing: uds.framing.framing(),
+                    mode: uds.mode,
+                };
+                servers.push(tokio::spawn(protocol::run_uds(
+                    uds.path.clone(),
+                    options,
+                    self.sessions.clone(),
+                    self.topics.clone(),
+                    self.shutdown.clone(),
+                )));
+            }
+            #[cfg(not(unix))]
+            anyhow::bail!("unix domain socket {:?} needs a unix target", uds.path);
+        }
+        Ok(())
+    }
+
+    fn serve_http(
+        &self,
+        name: &'static str,
+        addr: SocketAddr,
+        router: Router,
+    ) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+        // bind here, a taken address fails `run` right away
+        let server = axum::Server::try_bind(&addr)?;
+        let shutdown = self.shutdown.clone();
+        info!("{name} server start {addr}");
+        Ok(tokio::spawn(async move {
+            server
+                .serve(router.into_make_service())
+                .with_graceful_shutdown(async move { shutdown.wait().await })
+                .await?;
+            Ok(())
+        }))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::generate_uid;
+    use crate::protocol::test_utils::login_lines;
+    use crate::server::{ChatServerBuilder, ServerConfig, UdsConfig};
+    use crate::session::{SessionStore, TopicOptions};
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::UnixStream;
+
+    #[tokio::test]
+    async fn embedded_server() {
+        let path = std::env::temp_dir().join(format!("chat-{}.sock", generate_uid()));
+        let path = path.to_str().unwrap().to_string();
+        let config = ServerConfig {
+            uds_config: Some(UdsConfig {
+                path: path.clone(),
+                framing: Default::default(),
+                mode: None,
+            }),
+            ..Default::default()
+        };
+        let sessions = Arc::new(SessionStore::new());
+        let server = ChatServerBuilder::new(config)
+            .session_store(sessions.clone())
+            .build()
+            .unwrap();
+        assert!(Arc::ptr_eq(server.sessions(), &sessions));
+        let running = tokio::spawn(server.clone().run());
+
+        let stream = loop {
+            match UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::task::yield_now().await,
+            }
+        };
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        login_lines(&mut lines, &mut writer, "bot").await;
+        assert_eq!(sessions.find_by_user("bot").len(), 1);
+
+        server.shutdown();
+        let reply = lines.next_line().await.unwrap().unwrap();
+        assert!(reply.contains("\"going_away\""), "{reply}");
+        running.await.unwrap().unwrap();
+        assert!(sessions.list().is_empty());
+        // the uds listener stopped before `run` returned
+        assert!(std::fs::metadata(&path).is_err());
+    }
+
+    #[test]
+    fn load_example_config() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/server/config.toml");
+        let config = ServerConfig::load(path).unwrap();
+        assert!(config.ws_config.is_some());
+        assert_eq!(config.session_config.shutdown_deadline_secs, 5);
+    }
+
+    #[test]
+    fn partial_topic_config() {
+        let config: ServerConfig = toml::from_str("[topic_config]\nchannel_size = 8\n").unwrap();
+        let options = config.topic_config.options();
+        assert_eq!(options.channel_size, 8);
+        let defaults = TopicOptions::default();
+        assert_eq!(options.history_size, defaults.history_size);
+        assert_eq!(options.replay_size, defaults.replay_size);
+        assert_eq!(
+            ServerConfig::default().topic_config.replay_size,
+            defaults.replay_size
+        );
+    }
+}
@@ -0,0 +1,319 @@
+// ChatServerBuilder 的配置，一般从 toml 读取，见 examples/server/config.toml
+// 每种传输一个可选的 section，没有就不启动
+
+use crate::auth::{
+    AnyNameAuthenticator, Authenticator, DuplicateLogin, FileAuthenticator,
+    StaticTokenAuthenticator,
+};
+use crate::protocol::Framing;
+use crate::session::{SlowConsumer, TopicOptions};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ServerConfig {
+    // websocket, sse and the static files
+    #[serde(default)]
+    pub ws_config: Option<WsConfig>,
+    // grpc ChatService
+    #[serde(default)]
+    pub grpc_config: Option<GrpcConfig>,
+    #[serde(default)]
+    pub quic_config: Option<QuicConfig>,
+    // raw tcp is off without it
+    #[serde(default)]
+    pub tcp_config: Option<TcpConfig>,
+    // unix domain socket for local sidecars, off without it
+    #[serde(default)]
+    pub uds_config: Option<UdsConfig>,
+    // rest admin api, off without it
+    #[serde(default)]
+    pub admin_config: Option<AdminConfig>,
+    #[serde(default)]
+    pub topic_config: TopicConfig,
+    #[serde(default)]
+    pub store_config: StoreConfig,
+    #[serde(default)]
+    pub auth_config: AuthConfig,
+    #[serde(default)]
+    pub session_config: SessionConfig,
+}
+
+impl ServerConfig {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SessionConfig {
+    // seconds a session survives a lost connection for `resume`, 0 disables it
+    #[serde(default)]
+    pub resume_grace_secs: u64,
+    // seconds to flush queued messages on SIGTERM/SIGINT before exiting
+    #[serde(default = "default_shutdown_deadline")]
+    pub shutdown_deadline_secs: u64,
+}
+
+fn default_shutdown_deadline() -> u64 {
+    5
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            resume_grace_secs: 0,
+            shutdown_deadline_secs: default_shutdown_deadline(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WsConfig {
+    pub addr: String,
+    // served for paths without a route, e.g. examples/ws_static
+    #[serde(default)]
+    pub static_dir: Option<String>,
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self {
+            addr: "0.0.0.0:8080".to_string(),
+            static_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GrpcConfig {
+    pub addr: String,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            addr: "0.0.0.0:8081".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QuicConfig {
+    pub addr: String,
+}
+
+impl Default for QuicConfig {
+    fn default() -> Self {
+        Self {
+            addr: "127.0.0.1:8433".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminConfig {
+    pub addr: String,
+    // grpc ChatAdmin service, same token
+    #[serde(default)]
+    pub grpc_addr: Option<String>,
+    // `Authorization: Bearer <token>` required by both, without it keep them on a private address
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TcpConfig {
+    pub addr: String,
+    // lines (newline-delimited json) or length_delimited (negotiated, protobuf or json)
+    #[serde(default)]
+    pub framing: FramingConfig,
+    // tls with the quic certificate
+    #[serde(default)]
+    pub tls: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UdsConfig {
+    pub path: String,
+    #[serde(default)]
+    pub framing: FramingConfig,
+    // permission bits of the socket file, e.g. 0o660
+    #[serde(default)]
+    pub mode: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FramingConfig {
+    #[default]
+    Lines,
+    LengthDelimited,
+}
+
+impl FramingConfig {
+    pub fn framing(&self) -> Framing {
+        match self {
+            FramingConfig::Lines => Framing::Lines,
+            FramingConfig::LengthDelimited => Framing::LengthDelimited,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TopicConfig {
+    #[serde(default = "default_history_size")]
+    pub history_size: usize,
+    #[serde(default = "default_replay_size")]
+    pub replay_size: usize,
+    #[serde(default = "default_channel_size")]
+    pub channel_size: usize,
+    #[serde(default)]
+    pub slow_consumer: SlowConsumerConfig,
+    #[serde(default = "default_dedup_window")]
+    pub dedup_window: usize,
+}
+
+// the same as TopicOptions
+fn default_history_size() -> usize {
+    TopicOptions::default().history_size
+}
+
+fn default_replay_size() -> usize {
+    TopicOptions::default().replay_size
+}
+
+fn default_channel_size() -> usize {
+    TopicOptions::default().channel_size
+}
+
+fn default_dedup_window() -> usize {
+    TopicOptions::default().dedup_window
+}
+
+impl Default for TopicConfig {
+    fn default() -> Self {
+        Self {
+            history_size: default_history_size(),
+            replay_size: default_replay_size(),
+            channel_size: default_channel_size(),
+            slow_consumer: SlowConsumerConfig::default(),
+            dedup_window: default_dedup_window(),
+        }
+    }
+}
+
+impl TopicConfig {
+    pub fn options(&self) -> TopicOptions {
+        TopicOptions {
+            history_size: self.history_size,
+            replay_size: self.replay_size,
+            channel_size: self.channel_size,
+            dedup_window: self.dedup_window,
+            slow_consumer: match self.slow_consumer {
+                SlowConsumerConfig::DropOldest => SlowConsumer::DropOldest,
+                SlowConsumerConfig::Disconnect => SlowConsumer::Disconnect,
+                SlowConsumerConfig::Block { deadline_ms } => {
+                    SlowConsumer::Block(Duration::from_millis(deadline_ms))
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SlowConsumerConfig {
+    // skip missed messages, the client gets a gap event
+    #[default]
+    DropOldest,
+    Disconnect,
+    // wait `deadline_ms` for the client to take each message, closed like disconnect
+    // once it is `channel_size` messages behind
+    Block {
+        deadline_ms: u64,
+    },
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StoreConfig {
+    // messages are lost on restart
+    #[default]
+    Memory,
+    // one log per topic under `dir`, compacted to the last `history_size` messages
+    File {
+        dir: String,
+    },
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub authenticator: AuthenticatorConfig,
+    // allow, reject or kick_old
+    #[serde(default)]
+    pub duplicate_login: DuplicateLoginConfig,
+    #[serde(default)]
+    pub token: Option<TokenConfig>,
+}
+
+// signed session tokens, issued on login and accepted at connect time
+#[derive(Debug, Deserialize)]
+pub struct TokenConfig {
+    pub secret: String,
+    pub ttl_secs: u64,
+    // reject connections without a valid token
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AuthenticatorConfig {
+    // any non-empty name
+    #[default]
+    AnyName,
+    // `name:rounds:salt:pbkdf2_hmac_sha256(password, salt, rounds)` lines
+    File {
+        path: String,
+    },
+    // key: token, value: user name
+    Token {
+        tokens: HashMap<String, String>,
+    },
+}
+
+impl AuthenticatorConfig {
+    pub fn authenticator(&self) -> anyhow::Result<Arc<dyn Authenticator>> {
+        Ok(match self {
+            AuthenticatorConfig::AnyName => Arc::new(AnyNameAuthenticator),
+            AuthenticatorConfig::File { path } => Arc::new(FileAuthenticator::open(path)?),
+            AuthenticatorConfig::Token { tokens } => {
+                Arc::new(StaticTokenAuthenticator::new(tokens.clone()))
+            }
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateLoginConfig {
+    #[default]
+    Allow,
+    Reject,
+    KickOld,
+}
+
+impl DuplicateLoginConfig {
+    pub fn policy(&self) -> DuplicateLogin {
+        match self {
+            DuplicateLoginConfig::Allow => DuplicateLogin::Allow,
+            DuplicateLoginConfig::Reject => DuplicateLogin::Reject,
+            DuplicateLoginConfig::KickOld => DuplicateLogin::KickOld,
+        }
+    }
+}
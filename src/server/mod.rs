@@ -0,0 +1,5 @@
+mod builder;
+mod config;
+
+pub use self::builder::*;
+pub use self::config::*;
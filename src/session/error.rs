@@ -0,0 +1,60 @@
+// 返回给客户端的错误
+
+use crate::wire::{Error, ErrorCode};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ChatError {
+    #[error("topic not found: {0}")]
+    TopicNotFound(String),
+    #[error("not subscribed to topic: {0}")]
+    NotSubscribed(String),
+    #[error("invalid message: {0}")]
+    InvalidMessage(String),
+    #[error("login required")]
+    Unauthenticated,
+    #[error("invalid credentials for user: {0}")]
+    InvalidCredentials(String),
+    #[error("invalid token")]
+    InvalidToken,
+    #[error("token expired")]
+    TokenExpired,
+    #[error("already logged in: {0}")]
+    AlreadyLoggedIn(String),
+    #[error("user offline: {0}")]
+    UserOffline(String),
+    #[error("topic already exists: {0}")]
+    TopicExists(String),
+    #[error("only the owner can do this to topic: {0}")]
+    PermissionDenied(String),
+}
+
+impl ChatError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ChatError::TopicNotFound(_) => ErrorCode::TopicNotFound,
+            ChatError::NotSubscribed(_) => ErrorCode::NotSubscribed,
+            ChatError::InvalidMessage(_) => ErrorCode::InvalidMessage,
+            ChatError::Unauthenticated
+            | ChatError::InvalidCredentials(_)
+            | ChatError::InvalidToken
+            | ChatError::TokenExpired => ErrorCode::Unauthenticated,
+            ChatError::AlreadyLoggedIn(_) => ErrorCode::AlreadyLoggedIn,
+            ChatError::UserOffline(_) => ErrorCode::UserOffline,
+            ChatError::TopicExists(_) => ErrorCode::TopicExists,
+            ChatError::PermissionDenied(_) => ErrorCode::PermissionDenied,
+        }
+    }
+}
+
+impl From<&anyhow::Error> for Error {
+    fn from(err: &anyhow::Error) -> Self {
+        let code = err
+            .downcast_ref::<ChatError>()
+            .map(ChatError::code)
+            .unwrap_or(ErrorCode::Internal);
+        Error {
+            code: code as i32,
+            message: err.to_string(),
+        }
+    }
+}
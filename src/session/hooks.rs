@@ -0,0 +1,24 @@
+// 嵌入方的回调，在 session 任务里同步调用，不应阻塞
+
+use crate::wire::ServerMessage;
+
+// every method does nothing by default
+pub trait ChatHooks: Send + Sync {
+    // a transport opened a session, not authenticated yet
+    fn on_connect(&self, _session_id: &str) {}
+
+    // the session is `user_name` now, by login, by its transport or by resume
+    fn on_login(&self, _session_id: &str, _user_name: &str) {}
+
+    // before a chat message of a client is published to `topic`, an error rejects it
+    fn on_message(&self, _topic: &str, _msg: &ServerMessage) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    // the session is gone, `user_name` is empty if it never logged in
+    fn on_disconnect(&self, _session_id: &str, _user_name: &str) {}
+}
+
+pub struct NoHooks;
+
+impl ChatHooks for NoHooks {}
@@ -1,58 +1,282 @@
-use crate::session::topic::Topic;
+use crate::auth::{AnyNameAuthenticator, Authenticator, DuplicateLogin, TokenSigner};
+use crate::session::error::ChatError;
+use crate::session::hooks::{ChatHooks, NoHooks};
+use crate::session::metrics::SlowConsumerMetrics;
+use crate::session::presence::PresenceTracker;
+use crate::session::topic::{SlowConsumer, Subscription, Topic, TopicOptions};
 use crate::session::Session;
-use crate::wire::ServerMessage;
-use dashmap::DashMap;
+use crate::storage::{MemoryStore, MessageStore, StoredRoom};
+use crate::utils::generate_uid;
+use crate::wire::{
+    CreateRoom, Event, Login, Presence, PresenceStatus, RoomDeleted, RoomInfo, ServerMessage,
+};
+use dashmap::mapref::entry::Entry;
+use dashmap::{DashMap, DashSet};
 use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::sync::broadcast::Receiver;
-use tracing::info;
+use tokio::sync::Notify;
+use tracing::{error, info};
 
 #[derive(Clone)]
-// key: topic_id, value: topic
-pub struct TopicStore(DashMap<String, Topic>);
+pub struct TopicStore {
+    // key: topic_id, value: topic
+    topics: DashMap<String, Topic>,
+    options: TopicOptions,
+    store: Arc<dyn MessageStore>,
+    metrics: Arc<SlowConsumerMetrics>,
+}
 
 impl TopicStore {
     pub fn new() -> TopicStore {
-        TopicStore(DashMap::new())
+        Self::with_options(TopicOptions::default())
+    }
+
+    pub fn with_options(options: TopicOptions) -> TopicStore {
+        TopicStore {
+            topics: DashMap::new(),
+            store: Arc::new(MemoryStore::new(options.history_size)),
+            options,
+            metrics: Arc::default(),
+        }
+    }
+
+    // restore the topics and sequences kept in `store`
+    pub fn with_store(
+        options: TopicOptions,
+        store: Arc<dyn MessageStore>,
+    ) -> anyhow::Result<TopicStore> {
+        let topics = DashMap::new();
+        for topic_id in store.list_topics()? {
+            let room = store.load_room(&topic_id)?;
+            // nobody is subscribed after a restart
+            if matches!(&room, Some(room) if !room.persistent) {
+                info!("drop empty topic: {topic_id}");
+                store.delete_topic(&topic_id)?;
+                continue;
+            }
+            let sequence = store.last_sequence(&topic_id)?;
+            info!("restore topic: {topic_id}, sequence: {sequence}");
+            let mut topic = Topic::new(topic_id.clone(), sequence, store.clone(), &options);
+            match room {
+                Some(room) => {
+                    topic.owner = room.owner;
+                    topic.persistent = room.persistent;
+                }
+                // stored before rooms were, stays until removed by the server
+                None => topic.persistent = true,
+            }
+            // a retry right after the restart is still acked, not published again
+            topic.restore_recent()?;
+            topics.insert(topic_id, topic);
+        }
+        Ok(TopicStore {
+            topics,
+            options,
+            store,
+            metrics: Arc::default(),
+        })
+    }
+
+    pub fn options(&self) -> &TopicOptions {
+        &self.options
+    }
+
+    // all rooms ordered by topic
+    pub fn list_rooms(&self) -> Vec<RoomInfo> {
+        let mut rooms: Vec<RoomInfo> = self.topics.iter().map(|topic| room_info(&topic)).collect();
+        rooms.sort_by(|a, b| a.topic.cmp(&b.topic));
+        rooms
+    }
+
+    pub fn room(&self, topic_id: &str) -> anyhow::Result<RoomInfo> {
+        self.topics
+            .get(topic_id)
+            .map(|topic| room_info(&topic))
+            .ok_or_else(|| ChatError::TopicNotFound(topic_id.into()).into())
+    }
+
+    // user names subscribed to the topic, ordered
+    pub fn members(&self, topic_id: &str) -> anyhow::Result<Vec<String>> {
+        let topic = self
+            .topics
+            .get(topic_id)
+            .ok_or_else(|| ChatError::TopicNotFound(topic_id.into()))?;
+        let mut members: Vec<String> = topic.subscribes.keys().cloned().collect();
+        members.sort();
+        Ok(members)
+    }
+
+    // policy of one topic, new topics get `options().slow_consumer`
+    pub fn set_slow_consumer(&self, topic_id: &str, policy: SlowConsumer) -> anyhow::Result<()> {
+        let mut topic = self
+            .topics
+            .get_mut(topic_id)
+            .ok_or_else(|| ChatError::TopicNotFound(topic_id.into()))?;
+        topic.slow_consumer = policy;
+        Ok(())
+    }
+
+    pub fn metrics(&self) -> &Arc<SlowConsumerMetrics> {
+        &self.metrics
     }
 
-    pub fn subscribe(&self, user_name: String, topic_id: &str) -> Receiver<ServerMessage> {
-        match self.0.get_mut(topic_id) {
-            None => {
-                let mut topic = Topic::new(topic_id.into());
-                let res = topic.subscribe(user_name);
-                self.0.insert(topic_id.into(), topic);
-                res
+    // new room owned by `owner`, fails if it exists
+    pub fn create(
+        &self,
+        topic_id: &str,
+        owner: String,
+        settings: &CreateRoom,
+    ) -> anyhow::Result<()> {
+        match self.topics.entry(topic_id.into()) {
+            Entry::Occupied(_) => Err(ChatError::TopicExists(topic_id.into()).into()),
+            Entry::Vacant(entry) => {
+                info!("create topic: {topic_id}, owner: {owner}");
+                let room = StoredRoom {
+                    owner,
+                    persistent: settings.persistent,
+                };
+                self.store.save_room(topic_id, &room)?;
+                let mut topic = Topic::new(topic_id.into(), 0, self.store.clone(), &self.options);
+                topic.owner = room.owner;
+                topic.persistent = room.persistent;
+                entry.insert(topic);
+                Ok(())
             }
-            Some(mut topic) => topic.subscribe(user_name),
         }
     }
 
+    pub fn subscribe(
+        &self,
+        user_name: String,
+        topic_id: &str,
+    ) -> anyhow::Result<Receiver<ServerMessage>> {
+        Ok(self.subscribe_with_replay(user_name, topic_id, 0)?.receiver)
+    }
+
+    // subscribe and take the last `replay` messages in one step, so nothing is missed in between
+    pub fn subscribe_with_replay(
+        &self,
+        user_name: String,
+        topic_id: &str,
+        replay: usize,
+    ) -> anyhow::Result<Subscription> {
+        let mut topic = self
+            .topics
+            .get_mut(topic_id)
+            .ok_or_else(|| ChatError::TopicNotFound(topic_id.into()))?;
+        let receiver = topic.subscribe(user_name);
+        let history = match replay {
+            0 => vec![],
+            replay => topic.history(0, replay).unwrap_or_else(|e| {
+                error!("replay topic {topic_id} error: {e:?}");
+                vec![]
+            }),
+        };
+        Ok(Subscription {
+            receiver,
+            history,
+            sequence: topic.sequence(),
+            slow_consumer: topic.slow_consumer,
+        })
+    }
+
     pub fn unsubscribe(&self, user_name: String, topic_id: &str) {
         info!("unsubscribe topic: {}, user: {}", topic_id, user_name);
-        let mut deleted = false;
-        if let Some(mut topic) = self.0.get_mut(topic_id) {
-            deleted = topic.unsubscribe(user_name) <= 0
+        if let Some(mut topic) = self.topics.get_mut(topic_id) {
+            topic.unsubscribe(user_name);
+        }
+        // the check and the removal are atomic, a concurrent join keeps the room
+        let removed = self
+            .topics
+            .remove_if(topic_id, |_, topic| {
+                topic.subscribes.is_empty() && !topic.persistent
+            })
+            .is_some();
+        if removed {
+            info!("remove empty topic: {topic_id}");
+            if let Err(e) = self.store.delete_topic(topic_id) {
+                error!("delete topic {topic_id} error: {e:?}");
+            }
+        }
+    }
+
+    // ephemeral event about `user_name`, no sequence and not stored
+    pub fn notify(&self, topic_id: &str, user_name: &str, event: Event) -> anyhow::Result<()> {
+        match self.topics.get(topic_id) {
+            None => Err(ChatError::TopicNotFound(topic_id.into()).into()),
+            Some(topic) => {
+                topic.notify(user_name, event);
+                Ok(())
+            }
+        }
+    }
+
+    // event about `user_name` to every topic the user is subscribed to
+    pub fn notify_user(&self, user_name: &str, event: Event) {
+        self.topics
+            .iter()
+            .filter(|topic| topic.subscribes.contains_key(user_name))
+            .for_each(|topic| topic.notify(user_name, event.clone()));
+    }
+
+    // owner only, members are told before the room goes away
+    pub fn delete(&self, topic_id: &str, user_name: &str) -> anyhow::Result<()> {
+        {
+            let topic = self
+                .topics
+                .get(topic_id)
+                .ok_or_else(|| ChatError::TopicNotFound(topic_id.into()))?;
+            if topic.owner.is_empty() || topic.owner != user_name {
+                return Err(ChatError::PermissionDenied(topic_id.into()).into());
+            }
+            topic.notify(user_name, Event::RoomDeleted(RoomDeleted {}));
         }
-        if deleted {
-            self.0.remove(topic_id);
+        self.remove(topic_id)
+    }
+
+    pub fn send_message(&self, topic_id: &str, message: ServerMessage) -> anyhow::Result<u64> {
+        match self.topics.get_mut(topic_id) {
+            None => Err(ChatError::TopicNotFound(topic_id.into()).into()),
+            Some(mut topic) => topic.publish_once(message),
         }
     }
 
-    pub fn send_message(&self, topic_id: &str, message: String) -> anyhow::Result<()> {
-        match self.0.get_mut(topic_id) {
-            None => Err(anyhow::anyhow!("topic not found: {topic_id}")),
-            Some(mut topic) => topic.publish(message),
+    pub fn history(
+        &self,
+        topic_id: &str,
+        before_sequence: u64,
+        limit: usize,
+    ) -> anyhow::Result<Vec<ServerMessage>> {
+        match self.topics.get(topic_id) {
+            None => Err(ChatError::TopicNotFound(topic_id.into()).into()),
+            Some(topic) => topic.history(before_sequence, limit),
         }
     }
+
+    // drop a topic together with its stored messages
+    pub fn remove(&self, topic_id: &str) -> anyhow::Result<()> {
+        info!("remove topic: {topic_id}");
+        self.topics.remove(topic_id);
+        self.store.delete_topic(topic_id)
+    }
+}
+
+impl Default for TopicStore {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Display for TopicStore {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "SessionStore: {{\n")?;
-        for item in self.0.iter() {
-            write!(
+        writeln!(f, "SessionStore: {{")?;
+        for item in self.topics.iter() {
+            writeln!(
                 f,
-                "  {}: s_id {} s_name {:?}\n",
+                "  {}: s_id {} s_name {:?}",
                 item.key(),
                 item.value().id,
                 item.value().subscribes,
@@ -62,39 +286,382 @@ impl Display for TopicStore {
     }
 }
 
+fn room_info(topic: &Topic) -> RoomInfo {
+    RoomInfo {
+        topic: topic.id.clone(),
+        members: topic.subscribes.len() as u32,
+        owner: topic.owner.clone(),
+        persistent: topic.persistent,
+    }
+}
+
 pub struct SessionStore {
     // key: session_id, value: session
     sessions: DashMap<String, Session>,
+    authenticator: Arc<dyn Authenticator>,
+    duplicate_login: DuplicateLogin,
+    // serialize logins so the duplicate check and the rename are atomic
+    login_lock: Mutex<()>,
+    // issues tokens on login and verifies them at connection time
+    token_signer: Option<Arc<TokenSigner>>,
+    // reject connections without a token
+    token_required: bool,
+    presence: PresenceTracker,
+    // key: resume token, value: session_id
+    resume_tokens: DashMap<String, String>,
+    // how long a session outlives its transport, zero disables resume
+    resume_grace: Duration,
+    // session ids kept for resume without a transport
+    detached: DashSet<String>,
+    // transports still writing to their client
+    connections: Arc<ConnectionCount>,
+    hooks: Arc<dyn ChatHooks>,
+}
+
+#[derive(Default)]
+struct ConnectionCount {
+    open: AtomicUsize,
+    closed: Notify,
+}
+
+// held by a transport while it writes to its client, see `SessionStore::drained`
+pub struct ConnectionGuard {
+    count: Arc<ConnectionCount>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if self.count.open.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.count.closed.notify_waiters();
+        }
+    }
 }
 
 impl SessionStore {
     pub fn new() -> Self {
+        Self::with_auth(Arc::new(AnyNameAuthenticator), DuplicateLogin::Allow)
+    }
+
+    pub fn with_auth(
+        authenticator: Arc<dyn Authenticator>,
+        duplicate_login: DuplicateLogin,
+    ) -> Self {
         SessionStore {
             sessions: DashMap::new(),
+            authenticator,
+            duplicate_login,
+            login_lock: Mutex::new(()),
+            token_signer: None,
+            token_required: false,
+            presence: PresenceTracker::new(),
+            resume_tokens: DashMap::new(),
+            resume_grace: Duration::ZERO,
+            detached: DashSet::new(),
+            connections: Arc::default(),
+            hooks: Arc::new(NoHooks),
+        }
+    }
+
+    pub fn with_token_signer(mut self, signer: Arc<TokenSigner>, required: bool) -> Self {
+        self.token_signer = Some(signer);
+        self.token_required = required;
+        self
+    }
+
+    pub fn with_resume_grace(mut self, grace: Duration) -> Self {
+        self.resume_grace = grace;
+        self
+    }
+
+    pub fn with_hooks(mut self, hooks: Arc<dyn ChatHooks>) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    pub(crate) fn hooks(&self) -> &dyn ChatHooks {
+        self.hooks.as_ref()
+    }
+
+    // user of the token presented when connecting, None for an anonymous connection
+    pub fn verify_connection(&self, token: Option<&str>) -> anyhow::Result<Option<String>> {
+        match (&self.token_signer, token) {
+            (Some(signer), Some(token)) => Ok(Some(signer.verify(token)?)),
+            (_, None) if self.token_required => Err(ChatError::Unauthenticated.into()),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn issue_token(&self, user_name: &str) -> Option<String> {
+        self.token_signer
+            .as_ref()
+            .map(|signer| signer.issue(user_name))
+    }
+
+    // authenticate `sess` and apply the duplicate login policy, returns the user name
+    pub async fn login(&self, sess: &Session, login: &Login) -> anyhow::Result<String> {
+        let user_name = match &self.token_signer {
+            // the transport could not check it before, nothing else is accepted
+            Some(signer) if self.token_required => signer.verify(&login.token)?,
+            // a signed token from an earlier login
+            Some(signer) if !login.token.is_empty() => match signer.verify(&login.token) {
+                Ok(user_name) => user_name,
+                Err(_) => self.authenticate(login).await?,
+            },
+            _ => self.authenticate(login).await?,
+        };
+        self.attach_user(sess, user_name)
+    }
+
+    // password hashing is slow on purpose, so it stays off the runtime workers
+    async fn authenticate(&self, login: &Login) -> anyhow::Result<String> {
+        let authenticator = self.authenticator.clone();
+        let login = login.clone();
+        tokio::task::spawn_blocking(move || authenticator.authenticate(&login)).await?
+    }
+
+    // bind an authenticated user to `sess` under the duplicate login policy
+    pub fn attach_user(&self, sess: &Session, user_name: String) -> anyhow::Result<String> {
+        let _guard = self.login_lock.lock().unwrap();
+        let others: Vec<Session> = self
+            .find_by_user(&user_name)
+            .into_iter()
+            .filter(|other| other.id != sess.id)
+            .collect();
+        match self.duplicate_login {
+            DuplicateLogin::Allow => {}
+            // a detached session waits for a resume of this same user
+            DuplicateLogin::Reject => {
+                if others
+                    .iter()
+                    .any(|other| !self.detached.contains(&other.id))
+                {
+                    return Err(ChatError::AlreadyLoggedIn(user_name).into());
+                }
+            }
+            DuplicateLogin::KickOld => others
+                .iter()
+                .for_each(|other| other.kick("logged in from another session")),
+        }
+        sess.set_user_name(user_name.clone());
+        if let Some(presence) = self.presence.connect(&user_name) {
+            self.publish_presence(sess, &user_name, presence);
+        }
+        self.hooks.on_login(&sess.id, &user_name);
+        Ok(user_name)
+    }
+
+    // token to resume `sess` after its transport is lost, None when resume is disabled
+    pub fn issue_resume_token(&self, sess: &Session) -> Option<String> {
+        if self.resume_grace.is_zero() {
+            return None;
+        }
+        let token = generate_uid();
+        self.resume_tokens.insert(token.clone(), sess.id.clone());
+        Some(token)
+    }
+
+    // take over the session of `token` for `sess`, returns the old session
+    pub fn resume(&self, sess: &Session, token: &str) -> anyhow::Result<Session> {
+        // set when the transport authenticated the connection
+        let current = sess.user_name();
+        let old_id = self
+            .resume_tokens
+            .get(token)
+            .map(|item| item.value().clone())
+            .ok_or(ChatError::InvalidToken)?;
+        // closed by the server, on its way out, see `release`, or of another user
+        let (_, old) = self
+            .sessions
+            .remove_if(&old_id, |_, old| {
+                !old.is_closed_by_server() && (current.is_empty() || old.user_name() == current)
+            })
+            .ok_or(ChatError::InvalidToken)?;
+        self.resume_tokens.retain(|_, id| *id != old_id);
+        self.detached.remove(&old_id);
+        // the old transport may not have noticed the loss yet
+        old.close();
+        // the same client, so no duplicate login policy and no presence change
+        let user_name = old.user_name();
+        info!("session {} resumes {old_id} of {user_name}", sess.id);
+        self.hooks.on_disconnect(&old_id, &user_name);
+        if current.is_empty() {
+            // the connection count of the old session carries over to `sess`
+            sess.set_user_name(user_name.clone());
+            self.hooks.on_login(&sess.id, &user_name);
+        } else if let Some(presence) = self.presence.disconnect(&user_name) {
+            // counted for both, `sess` stays
+            self.publish_presence(sess, &user_name, presence);
+        }
+        Ok(old)
+    }
+
+    pub fn presence(&self, user_name: &str) -> Presence {
+        self.presence.get(user_name)
+    }
+
+    pub fn set_presence(&self, sess: &Session, status: PresenceStatus) {
+        let user_name = sess.user_name();
+        if let Some(presence) = self.presence.set_status(&user_name, status) {
+            self.publish_presence(sess, &user_name, presence);
+        }
+    }
+
+    // to the rooms of the user and to the sessions watching it
+    fn publish_presence(&self, sess: &Session, user_name: &str, presence: Presence) {
+        info!("presence of {user_name}: {presence:?}");
+        sess.topics()
+            .notify_user(user_name, Event::Presence(presence.clone()));
+        let mut msg = ServerMessage::event("", Event::Presence(presence));
+        msg.user_name = user_name.to_string();
+        self.sessions
+            .iter()
+            .filter(|watcher| watcher.watches(user_name))
+            .for_each(|watcher| watcher.push(msg.clone()));
+    }
+
+    // authenticated sessions of `user_name`
+    pub fn find_by_user(&self, user_name: &str) -> Vec<Session> {
+        if user_name.is_empty() {
+            return vec![];
+        }
+        self.sessions
+            .iter()
+            .filter(|item| item.value().user_name() == user_name)
+            .map(|item| item.value().clone())
+            .collect()
+    }
+
+    pub fn open_connection(&self) -> ConnectionGuard {
+        self.connections.open.fetch_add(1, Ordering::AcqRel);
+        ConnectionGuard {
+            count: self.connections.clone(),
+        }
+    }
+
+    pub fn connections(&self) -> usize {
+        self.connections.open.load(Ordering::Acquire)
+    }
+
+    // until no transport writes to a client anymore
+    pub async fn drained(&self) {
+        loop {
+            // registered before the check, so a close in between is not missed
+            let closed = self.connections.closed.notified();
+            if self.connections() == 0 {
+                return;
+            }
+            closed.await;
+        }
+    }
+
+    // server shutdown: every session gets a going_away event and is closed
+    pub fn go_away(&self, reason: &str) {
+        info!("closing {} sessions: {reason}", self.sessions.len());
+        for sess in self.list() {
+            sess.go_away(reason);
+            // detached ones have no transport left to release them
+            self.remove(sess.id);
+        }
+    }
+
+    // all sessions ordered by id, detached resumable ones included
+    pub fn list(&self) -> Vec<Session> {
+        let mut sessions: Vec<Session> = self
+            .sessions
+            .iter()
+            .map(|item| item.value().clone())
+            .collect();
+        sessions.sort_by(|a, b| a.id.cmp(&b.id));
+        sessions
+    }
+
+    // close the session for good, it can not be resumed
+    pub fn disconnect(&self, sess_id: &str, reason: &str) -> bool {
+        match self.remove(sess_id.to_string()) {
+            Some((_, sess)) => {
+                sess.kick(reason);
+                true
+            }
+            None => false,
         }
     }
 
     pub fn add(&self, sess: Session) {
-        if self.sessions.get(&sess.id).is_none() {
-            self.sessions.insert(sess.id.clone(), sess);
+        if let Entry::Vacant(entry) = self.sessions.entry(sess.id.clone()) {
+            self.hooks.on_connect(&sess.id);
+            entry.insert(sess);
+        }
+    }
+
+    // add a session whose user was authenticated when connecting
+    pub fn add_with_user(&self, sess: Session, user_name: Option<String>) -> anyhow::Result<()> {
+        if let Some(user_name) = user_name {
+            self.attach_user(&sess, user_name)?;
         }
+        self.add(sess);
+        Ok(())
+    }
+
+    // the transport of the session is gone, a resumable session is kept for the grace period
+    pub fn release(self: &Arc<Self>, sess_id: String) {
+        let closed_by_server = self
+            .sessions
+            .get(&sess_id)
+            .is_some_and(|sess| sess.is_closed_by_server());
+        let resumable = !self.resume_grace.is_zero()
+            && !closed_by_server
+            && self
+                .resume_tokens
+                .iter()
+                .any(|item| *item.value() == sess_id);
+        if !resumable {
+            self.remove(sess_id);
+            return;
+        }
+        info!(
+            "session {sess_id} detached, kept for {:?}",
+            self.resume_grace
+        );
+        self.detached.insert(sess_id.clone());
+        let store = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(store.resume_grace).await;
+            // gone already when resumed
+            store.remove(sess_id);
+        });
     }
 
     pub fn remove(&self, sess_id: String) -> Option<(String, Session)> {
-        self.sessions.remove(&sess_id)
+        self.resume_tokens.retain(|_, id| *id != sess_id);
+        self.detached.remove(&sess_id);
+        let removed = self.sessions.remove(&sess_id);
+        if let Some((_, sess)) = &removed {
+            let user_name = sess.user_name();
+            if let Some(presence) = self.presence.disconnect(&user_name) {
+                self.publish_presence(sess, &user_name, presence);
+            }
+            self.hooks.on_disconnect(&sess.id, &user_name);
+        }
+        removed
+    }
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl Display for SessionStore {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "SessionStore: {{\n")?;
+        writeln!(f, "SessionStore: {{")?;
         for item in self.sessions.iter() {
-            write!(
+            writeln!(
                 f,
-                "  {}: s_id {} s_name {}\n",
+                "  {}: s_id {} s_name {}",
                 item.key(),
                 item.value().id,
-                item.value().user_name,
+                item.value().user_name(),
             )?;
         }
         write!(f, "}}")
@@ -103,8 +670,12 @@ impl Display for SessionStore {
 
 #[cfg(test)]
 mod tests {
+    use crate::generate_uid;
     use crate::session::hub::TopicStore;
-    use crate::wire::ServerMessage;
+    use crate::session::topic::TopicOptions;
+    use crate::storage::FileStore;
+    use crate::wire::{ChatMessage, CreateRoom, Event, MemberJoined, RoomDeleted, ServerMessage};
+    use std::sync::Arc;
 
     #[tokio::test]
     async fn topic_store_subscribe() {
@@ -113,27 +684,221 @@ mod tests {
         let topic_id = "topic_id";
         let user_name = "user_name";
 
-        let mut res = store.subscribe(user_name.into(), topic_id);
+        store
+            .create(topic_id, user_name.into(), &CreateRoom::default())
+            .unwrap();
+        let mut res = store.subscribe(user_name.into(), topic_id).unwrap();
 
-        store.send_message(topic_id, "xxx".to_string()).unwrap();
+        store
+            .send_message(
+                topic_id,
+                ServerMessage {
+                    event: Some(ChatMessage("xxx".to_string())),
+                    user_name: user_name.to_string(),
+                    session_id: "session_id".to_string(),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        let joined = res.recv().await.unwrap();
+        assert_eq!(joined.sequence, 0);
+        assert_eq!(joined.event, Some(Event::MemberJoined(MemberJoined {})));
 
         let result = res.recv().await.unwrap();
+        assert!(result.timestamp > 0);
         assert_eq!(
             result,
             ServerMessage {
                 sequence: 1,
                 topic: "topic_id".to_string(),
-                message: Some("xxx".to_string())
+                event: Some(ChatMessage("xxx".to_string())),
+                user_name: user_name.to_string(),
+                session_id: "session_id".to_string(),
+                timestamp: result.timestamp,
+                ..Default::default()
             }
         );
     }
 
+    #[tokio::test]
+    async fn topic_store_history() {
+        let store = TopicStore::with_options(TopicOptions {
+            history_size: 3,
+            replay_size: 2,
+            ..Default::default()
+        });
+        let topic_id = "topic_id";
+
+        store
+            .create(topic_id, "user_a".into(), &CreateRoom::default())
+            .unwrap();
+        let _res = store.subscribe("user_a".into(), topic_id).unwrap();
+        for i in 1..=4 {
+            let msg = ServerMessage {
+                event: Some(ChatMessage(format!("msg {i}"))),
+                ..Default::default()
+            };
+            store.send_message(topic_id, msg).unwrap();
+        }
+
+        // oldest message is evicted
+        let sequences =
+            |msgs: Vec<ServerMessage>| msgs.iter().map(|m| m.sequence).collect::<Vec<_>>();
+        assert_eq!(
+            sequences(store.history(topic_id, 0, 0).unwrap()),
+            vec![2, 3, 4]
+        );
+        assert_eq!(sequences(store.history(topic_id, 4, 1).unwrap()), vec![3]);
+        assert!(store.history("unknown", 0, 0).is_err());
+
+        let sub = store
+            .subscribe_with_replay("user_b".into(), topic_id, 2)
+            .unwrap();
+        assert_eq!(sequences(sub.history), vec![3, 4]);
+        assert_eq!(sub.sequence, 4);
+    }
+
     #[test]
     fn topics_drop() {
         let store = TopicStore::new();
         let topic_id = "topic_id";
         let user_name = "user_name";
-        store.subscribe(user_name.into(), topic_id);
+        store
+            .create(topic_id, user_name.into(), &CreateRoom::default())
+            .unwrap();
+        store.subscribe(user_name.into(), topic_id).unwrap();
         store.unsubscribe(user_name.into(), topic_id);
+        // not persistent, dropped with its last member
+        assert!(store.subscribe(user_name.into(), topic_id).is_err());
+    }
+
+    #[tokio::test]
+    async fn room_lifecycle() {
+        let store = TopicStore::new();
+        let persistent = CreateRoom { persistent: true };
+        assert!(store.subscribe("bob".into(), "room").is_err());
+        store.create("room", "alice".into(), &persistent).unwrap();
+        assert!(store.create("room", "bob".into(), &persistent).is_err());
+
+        let mut res = store.subscribe("bob".into(), "room").unwrap();
+        store.unsubscribe("bob".into(), "room");
+        // persistent rooms outlive their members
+        let _alice = store.subscribe("alice".into(), "room").unwrap();
+
+        let rooms = store.list_rooms();
+        assert_eq!(rooms.len(), 1);
+        assert_eq!((rooms[0].members, rooms[0].owner.as_str()), (1, "alice"));
+        assert_eq!(store.members("room").unwrap(), vec!["alice".to_string()]);
+        assert!(store.members("unknown").is_err());
+
+        assert!(store.delete("room", "bob").is_err());
+        store.delete("room", "alice").unwrap();
+        assert!(store.subscribe("bob".into(), "room").is_err());
+
+        let events: Vec<_> = std::iter::from_fn(|| res.try_recv().ok())
+            .map(|msg| msg.event)
+            .collect();
+        assert_eq!(
+            events.last(),
+            Some(&Some(Event::RoomDeleted(RoomDeleted {})))
+        );
+    }
+
+    #[test]
+    fn restore_rooms() {
+        let dir = std::env::temp_dir().join(generate_uid());
+        let open = || {
+            let store = Arc::new(FileStore::open(&dir, 10).unwrap());
+            TopicStore::with_store(TopicOptions::default(), store).unwrap()
+        };
+        let store = open();
+        store
+            .create("kept", "alice".into(), &CreateRoom { persistent: true })
+            .unwrap();
+        store
+            .create("temp", "alice".into(), &CreateRoom::default())
+            .unwrap();
+        let msg = ServerMessage {
+            event: Some(ChatMessage("hi".into())),
+            ..Default::default()
+        };
+        store.send_message("temp", msg).unwrap();
+        drop(store);
+
+        // the empty room is gone, the persistent one keeps its owner
+        let store = open();
+        let rooms = store.list_rooms();
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(
+            (rooms[0].topic.as_str(), rooms[0].owner.as_str()),
+            ("kept", "alice")
+        );
+        assert!(rooms[0].persistent);
+        store.delete("kept", "alice").unwrap();
+        drop(store);
+        assert!(open().list_rooms().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restore_dedup() {
+        let dir = std::env::temp_dir().join(generate_uid());
+        let open = || {
+            let store = Arc::new(FileStore::open(&dir, 10).unwrap());
+            TopicStore::with_store(TopicOptions::default(), store).unwrap()
+        };
+        let send = |store: &TopicStore| {
+            let msg = ServerMessage {
+                event: Some(ChatMessage("hi".into())),
+                user_name: "alice".into(),
+                message_id: "m1".into(),
+                ..Default::default()
+            };
+            store.send_message("room", msg).unwrap()
+        };
+        let store = open();
+        store
+            .create("room", "alice".into(), &CreateRoom { persistent: true })
+            .unwrap();
+        assert_eq!(send(&store), 1);
+        drop(store);
+
+        let store = open();
+        assert_eq!(send(&store), 1);
+        assert_eq!(store.history("room", 0, 0).unwrap().len(), 1);
+        drop(store);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn send_message_once() {
+        let store = TopicStore::with_options(TopicOptions {
+            dedup_window: 2,
+            ..Default::default()
+        });
+        store
+            .create("room", "alice".into(), &CreateRoom::default())
+            .unwrap();
+        let send = |user_name: &str, message_id: &str| {
+            let msg = ServerMessage {
+                event: Some(ChatMessage("hi".into())),
+                user_name: user_name.into(),
+                message_id: message_id.into(),
+                ..Default::default()
+            };
+            store.send_message("room", msg).unwrap()
+        };
+        assert_eq!(send("alice", "m1"), 1);
+        // a retry gets the first sequence
+        assert_eq!(send("alice", "m1"), 1);
+        // ids are per user, no id is never deduplicated
+        assert_eq!(send("bob", "m1"), 2);
+        assert_eq!(send("alice", ""), 3);
+        assert_eq!(send("alice", ""), 4);
+        // m1 of alice falls out of the window
+        assert_eq!(send("alice", "m2"), 5);
+        assert_eq!(send("alice", "m1"), 6);
+        assert_eq!(store.history("room", 0, 0).unwrap().len(), 6);
     }
 }
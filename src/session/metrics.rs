@@ -0,0 +1,39 @@
+// 慢消费者统计
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Default)]
+pub struct SlowConsumerMetrics {
+    // times a subscriber fell behind the topic channel
+    lagged: AtomicU64,
+    // messages skipped by lagging subscribers
+    skipped: AtomicU64,
+    // sessions disconnected for being too slow
+    disconnects: AtomicU64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SlowConsumerStats {
+    pub lagged: u64,
+    pub skipped: u64,
+    pub disconnects: u64,
+}
+
+impl SlowConsumerMetrics {
+    pub fn record_lag(&self, skipped: u64) {
+        self.lagged.fetch_add(1, Ordering::Relaxed);
+        self.skipped.fetch_add(skipped, Ordering::Relaxed);
+    }
+
+    pub fn record_disconnect(&self) {
+        self.disconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> SlowConsumerStats {
+        SlowConsumerStats {
+            lagged: self.lagged.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            disconnects: self.disconnects.load(Ordering::Relaxed),
+        }
+    }
+}
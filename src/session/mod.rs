@@ -1,7 +1,15 @@
+mod error;
+mod hooks;
 mod hub;
+mod metrics;
+mod presence;
 mod sessions;
 mod topic;
 
+pub use self::error::*;
+pub use self::hooks::*;
 pub use self::hub::*;
+pub use self::metrics::*;
+pub use self::presence::*;
 pub use self::sessions::*;
 pub use self::topic::*;
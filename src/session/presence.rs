@@ -0,0 +1,115 @@
+// 用户在线状态，按 user name 记录
+
+use crate::utils::timestamp_millis;
+use crate::wire::{Presence, PresenceStatus};
+use dashmap::DashMap;
+
+struct UserPresence {
+    status: PresenceStatus,
+    // unix millis of the last change, 0 if never seen
+    last_seen: u64,
+    // authenticated sessions of the user
+    sessions: usize,
+}
+
+impl Default for UserPresence {
+    fn default() -> Self {
+        UserPresence {
+            status: PresenceStatus::Offline,
+            last_seen: 0,
+            sessions: 0,
+        }
+    }
+}
+
+impl UserPresence {
+    fn to_presence(&self) -> Presence {
+        Presence {
+            status: self.status as i32,
+            last_seen: self.last_seen,
+        }
+    }
+}
+
+// every method returns the new presence only when the status changed
+#[derive(Default)]
+pub struct PresenceTracker {
+    // key: user_name
+    users: DashMap<String, UserPresence>,
+}
+
+impl PresenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // a session of the user authenticated
+    pub fn connect(&self, user_name: &str) -> Option<Presence> {
+        let mut user = self.users.entry(user_name.to_string()).or_default();
+        user.sessions += 1;
+        match user.status {
+            PresenceStatus::Offline => Some(Self::change(&mut user, PresenceStatus::Online)),
+            _ => None,
+        }
+    }
+
+    // an authenticated session of the user closed
+    pub fn disconnect(&self, user_name: &str) -> Option<Presence> {
+        let mut user = self.users.get_mut(user_name)?;
+        user.sessions = user.sessions.saturating_sub(1);
+        match user.sessions {
+            0 => Some(Self::change(&mut user, PresenceStatus::Offline)),
+            _ => None,
+        }
+    }
+
+    // status chosen by the client, only while the user is connected
+    pub fn set_status(&self, user_name: &str, status: PresenceStatus) -> Option<Presence> {
+        let mut user = self.users.get_mut(user_name)?;
+        match user.sessions > 0 && user.status != status {
+            true => Some(Self::change(&mut user, status)),
+            false => None,
+        }
+    }
+
+    // offline with last_seen 0 for unknown users
+    pub fn get(&self, user_name: &str) -> Presence {
+        self.users
+            .get(user_name)
+            .map(|user| user.to_presence())
+            .unwrap_or_default()
+    }
+
+    fn change(user: &mut UserPresence, status: PresenceStatus) -> Presence {
+        user.status = status;
+        user.last_seen = timestamp_millis();
+        user.to_presence()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::session::presence::PresenceTracker;
+    use crate::wire::PresenceStatus;
+
+    #[test]
+    fn presence_changes() {
+        let tracker = PresenceTracker::new();
+        assert_eq!(tracker.get("bob").status, PresenceStatus::Offline as i32);
+
+        let online = tracker.connect("bob").unwrap();
+        assert_eq!(online.status, PresenceStatus::Online as i32);
+        assert!(online.last_seen > 0);
+        // second session, still online
+        assert!(tracker.connect("bob").is_none());
+
+        assert!(tracker.set_status("bob", PresenceStatus::Idle).is_some());
+        assert!(tracker.set_status("bob", PresenceStatus::Idle).is_none());
+
+        assert!(tracker.disconnect("bob").is_none());
+        let offline = tracker.disconnect("bob").unwrap();
+        assert_eq!(offline.status, PresenceStatus::Offline as i32);
+        assert_eq!(tracker.get("bob"), offline);
+        assert!(tracker.set_status("bob", PresenceStatus::Online).is_none());
+    }
+}
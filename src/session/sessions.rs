@@ -1,98 +1,1242 @@
 // 保存单个 sessoin 和 session store
 
-use crate::session::hub::TopicStore;
+use crate::session::error::ChatError;
+use crate::session::hub::{SessionStore, TopicStore};
+use crate::session::metrics::SlowConsumerMetrics;
+use crate::session::topic::{SlowConsumer, Subscription};
 use crate::wire::client_message::Message;
-use crate::wire::{ClientMessage, ServerMessage};
-use dashmap::DashMap;
+use crate::wire::{
+    Ack, ChatMessage, ClientMessage, DirectMessage, Event, Gap, GoingAway, Kicked, MemberList,
+    PresenceStatus, RoomList, SendDirect, ServerMessage, Typing,
+};
+use dashmap::{DashMap, DashSet};
 
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::broadcast::Receiver;
+use tokio::sync::mpsc::error::SendTimeoutError;
 use tokio::sync::mpsc::{Receiver as TokioReceiver, Sender};
+use tokio::sync::Notify;
 use tokio::task::JoinHandle;
 use tracing::{error, info};
 
+// a typing indicator without a refresh stops after this
+const TYPING_EXPIRY: Duration = Duration::from_secs(5);
+
 #[derive(Clone)]
 pub struct Session {
     pub id: String,
-    pub user_name: String,
+    // empty until login succeeds
+    user_name: Arc<RwLock<String>>,
+    sessions: Arc<SessionStore>,
     topics: Arc<TopicStore>,
     output_stream: Sender<ServerMessage>,
-    subscriptions: Arc<DashMap<String, JoinHandle<()>>>,
+    subscriptions: Arc<Subscriptions>,
+    // users whose presence changes are pushed to this session
+    watching: Arc<DashSet<String>>,
+    // key: topic_id, value: timer which stops our typing indicator
+    typing: Arc<DashMap<String, JoinHandle<()>>>,
+    closed: Arc<Notify>,
+    // kicked or going away, never kept for resume
+    closed_by_server: Arc<AtomicBool>,
+}
+
+// topic subscriptions shared by all clones of a session, released with the last one
+struct Subscriptions {
+    user_name: Arc<RwLock<String>>,
+    topics: Arc<TopicStore>,
+    // key: topic_id, value: forward task
+    handles: DashMap<String, Forwarding>,
+}
+
+struct Forwarding {
+    task: JoinHandle<()>,
+    // set by the task once the room is deleted, a room of the same name is another one
+    room_gone: Arc<AtomicBool>,
+}
+
+impl Forwarding {
+    fn is_live(&self) -> bool {
+        !self.room_gone.load(Ordering::Acquire)
+    }
 }
 
 impl Session {
     pub fn new(
         id: String,
+        sessions: Arc<SessionStore>,
         topics: Arc<TopicStore>,
         output_stream: Sender<ServerMessage>,
     ) -> Session {
+        let user_name = Arc::new(RwLock::new(String::new()));
         Session {
             id,
-            user_name: String::new(),
+            subscriptions: Arc::new(Subscriptions {
+                user_name: user_name.clone(),
+                topics: topics.clone(),
+                handles: DashMap::new(),
+            }),
+            user_name,
             output_stream,
+            sessions,
             topics,
-            subscriptions: Arc::new(DashMap::new()),
+            watching: Arc::new(DashSet::new()),
+            typing: Arc::new(DashMap::new()),
+            closed: Arc::new(Notify::new()),
+            closed_by_server: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    pub fn user_name(&self) -> String {
+        self.user_name.read().unwrap().clone()
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        !self.user_name.read().unwrap().is_empty()
+    }
+
     // system send to user
     pub async fn send_message(&self, msg: ServerMessage) -> anyhow::Result<()> {
         Ok(self.output_stream.send(msg).await?)
     }
 
+    // stop `run`, the transport then drops the connection
+    pub fn close(&self) {
+        self.closed.notify_one();
+    }
+
+    // tell the user why and close the session, a full output stream skips the notice
+    pub fn kick(&self, reason: &str) {
+        info!("kick session {} {}: {reason}", self.id, self.user_name());
+        let event = Event::Kicked(Kicked {
+            reason: reason.to_string(),
+        });
+        self.push(ServerMessage::event("", event));
+        self.closed_by_server.store(true, Ordering::Release);
+        self.close();
+    }
+
+    pub fn is_closed_by_server(&self) -> bool {
+        self.closed_by_server.load(Ordering::Acquire)
+    }
+
+    // server shutdown, queued messages still go out before the transport closes
+    pub fn go_away(&self, reason: &str) {
+        let event = Event::GoingAway(GoingAway {
+            reason: reason.to_string(),
+        });
+        self.push(ServerMessage::event("", event));
+        self.closed_by_server.store(true, Ordering::Release);
+        self.close();
+    }
+
+    // server push from outside the session task, dropped when the output stream is full
+    pub(crate) fn push(&self, msg: ServerMessage) {
+        if let Err(e) = self.output_stream.try_send(msg) {
+            // no payload, it may be a private message
+            error!("push to session {} error: {e}", self.id);
+        }
+    }
+
+    pub(crate) fn set_user_name(&self, user_name: String) {
+        *self.user_name.write().unwrap() = user_name;
+    }
+
+    pub(crate) fn topics(&self) -> &Arc<TopicStore> {
+        &self.topics
+    }
+
+    pub(crate) fn watches(&self, user_name: &str) -> bool {
+        self.watching.contains(user_name)
+    }
+
     pub async fn run(
         &mut self,
         mut input_stream: TokioReceiver<ClientMessage>,
     ) -> anyhow::Result<()> {
-        while let Some(msg) = input_stream.recv().await {
-            if let Some(message) = msg.message {
-                match message {
-                    Message::JoinRoom(_) | Message::JoinUser(_) | Message::CreateRoom(_) => {
-                        if self.subscriptions.get(&msg.topic).is_none() {
-                            let receiver =
-                                self.topics.subscribe(self.user_name.clone(), &msg.topic);
-                            self.spawn(&msg.topic, receiver).await;
-                        }
-                    }
-                    Message::LeaveRoom(_) | Message::LeaveUser(_) => {
-                        if let Some((_, sub)) = self.subscriptions.remove(&msg.topic) {
-                            sub.abort();
-                            self.topics.unsubscribe(self.user_name.clone(), &msg.topic);
-                        }
-                    }
-                    Message::SendMessage(data) => {
-                        if self.subscriptions.get(&msg.topic).is_some() {
-                            self.topics.send_message(&msg.topic, data)?;
-                        }
+        loop {
+            let msg = tokio::select! {
+                msg = input_stream.recv() => match msg {
+                    Some(msg) => msg,
+                    None => break,
+                },
+                _ = self.closed.notified() => {
+                    info!("session {} closed", self.id);
+                    break;
+                }
+            };
+            // every command is answered, errors do not end the session
+            let result = match msg.message {
+                Some(message) => self.handle(&msg.topic, &msg.message_id, message).await,
+                // also commands this server no longer knows, like join_user
+                None => Err(ChatError::InvalidMessage("no known command".into()).into()),
+            };
+            let event = match result {
+                Ok(ack) => Event::Ack(ack),
+                Err(e) => {
+                    error!("session {} handle error: {e:?}", self.id);
+                    Event::Error((&e).into())
+                }
+            };
+            let mut reply = ServerMessage::event(&msg.topic, event);
+            reply.request_id = msg.request_id;
+            self.send_message(reply).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle(
+        &mut self,
+        topic: &str,
+        message_id: &str,
+        message: Message,
+    ) -> anyhow::Result<Ack> {
+        let mut ack = Ack::default();
+        if !matches!(message, Message::Login(_) | Message::Resume(_)) && !self.is_authenticated() {
+            return Err(ChatError::Unauthenticated.into());
+        }
+
+        match message {
+            Message::CreateRoom(data) => {
+                self.topics.create(topic, self.user_name(), &data)?;
+                self.join(topic).await?;
+            }
+            Message::JoinRoom(_) => self.join(topic).await?,
+            Message::DeleteRoom(_) => {
+                self.topics.delete(topic, &self.user_name())?;
+                // the forward task ends by itself after passing on room_deleted
+                self.subscriptions.handles.remove(topic);
+            }
+            Message::LeaveRoom(_) => {
+                self.check_subscribed(topic)?;
+                if let Some((_, sub)) = self.subscriptions.handles.remove(topic) {
+                    sub.task.abort();
+                }
+                self.topics.unsubscribe(self.user_name(), topic);
+            }
+            Message::SendMessage(data) => {
+                self.check_subscribed(topic)?;
+                let msg = ServerMessage {
+                    event: Some(ChatMessage(data)),
+                    user_name: self.user_name(),
+                    session_id: self.id.clone(),
+                    message_id: message_id.to_string(),
+                    ..Default::default()
+                };
+                self.sessions.hooks().on_message(topic, &msg)?;
+                // the message ends typing
+                self.set_typing(topic, false)?;
+                ack.sequence = self.topics.send_message(topic, msg)?;
+            }
+            Message::SendDirect(data) => self.send_direct(data)?,
+            Message::Typing(data) => {
+                self.check_subscribed(topic)?;
+                self.set_typing(topic, data.active)?;
+            }
+            Message::SubscribePresence(data) => {
+                self.watching.clear();
+                for user in data.users {
+                    let mut msg =
+                        ServerMessage::event("", Event::Presence(self.sessions.presence(&user)));
+                    msg.user_name = user.clone();
+                    self.send_message(msg).await?;
+                    self.watching.insert(user);
+                }
+            }
+            Message::SetPresence(data) => {
+                let status = PresenceStatus::from_i32(data.status)
+                    .ok_or_else(|| anyhow::anyhow!("unknown presence status {}", data.status))?;
+                self.sessions.set_presence(self, status);
+            }
+            Message::ListRooms(_) => {
+                ack.room_list = Some(RoomList {
+                    rooms: self.topics.list_rooms(),
+                });
+            }
+            Message::ListMembers(_) => {
+                ack.member_list = Some(MemberList {
+                    members: self.topics.members(topic)?,
+                });
+            }
+            Message::FetchHistory(data) => {
+                self.check_subscribed(topic)?;
+                let history =
+                    self.topics
+                        .history(topic, data.before_sequence, data.limit as usize)?;
+                for item in history {
+                    self.send_message(item).await?;
+                }
+            }
+            Message::Login(data) => {
+                // no rename within a session
+                if self.is_authenticated() {
+                    return Err(ChatError::AlreadyLoggedIn(self.user_name()).into());
+                }
+                let user_name = self.sessions.login(self, &data).await?;
+                info!("session {} login as {user_name}", self.id);
+                ack.token = self.sessions.issue_token(&user_name).unwrap_or_default();
+                ack.session_token = self.sessions.issue_resume_token(self).unwrap_or_default();
+            }
+            // also after the transport authenticated the same user
+            Message::Resume(data) => {
+                let old = self.sessions.resume(self, &data.session_token)?;
+                for topic in old.subscribed_topics() {
+                    let last_seen = data.last_seen.get(&topic).copied();
+                    if let Err(e) = self.rejoin(&topic, last_seen).await {
+                        error!("session {} rejoin {topic} error: {e:?}", self.id);
                     }
-                    Message::Login(data) => self.user_name = data.name,
                 }
+                // the old subscriptions go with the last clone, after ours are in place
+                drop(old);
+                let user_name = self.user_name();
+                ack.token = self.sessions.issue_token(&user_name).unwrap_or_default();
+                ack.session_token = self.sessions.issue_resume_token(self).unwrap_or_default();
+            }
+        }
+        Ok(ack)
+    }
+
+    async fn join(&mut self, topic: &str) -> anyhow::Result<()> {
+        if self.check_subscribed(topic).is_ok() {
+            return Ok(());
+        }
+        let mut sub = self.topics.subscribe_with_replay(
+            self.user_name(),
+            topic,
+            self.topics.options().replay_size,
+        )?;
+        for item in std::mem::take(&mut sub.history) {
+            self.send_message(item).await?;
+        }
+        self.spawn(topic, sub).await;
+        Ok(())
+    }
+
+    // join again after a resume, messages after `last_seen` are replayed
+    async fn rejoin(&mut self, topic: &str, last_seen: Option<u64>) -> anyhow::Result<()> {
+        let sub = self
+            .topics
+            .subscribe_with_replay(self.user_name(), topic, 0)?;
+        if let Some(last_seen) = last_seen.filter(|seq| *seq < sub.sequence) {
+            let missed = (sub.sequence - last_seen) as usize;
+            let history = self.topics.history(topic, sub.sequence + 1, missed)?;
+            // the store may not keep all of them
+            let first = history.first().map_or(sub.sequence + 1, |msg| msg.sequence);
+            if first > last_seen + 1 {
+                let gap = Gap {
+                    from_sequence: last_seen + 1,
+                    to_sequence: first - 1,
+                };
+                self.send_message(ServerMessage::event(topic, Event::Gap(gap)))
+                    .await?;
+            }
+            for item in history {
+                self.send_message(item).await?;
+            }
+        }
+        self.spawn(topic, sub).await;
+        Ok(())
+    }
+
+    // topics with a live subscription, ordered
+    pub fn subscribed_topics(&self) -> Vec<String> {
+        let mut topics: Vec<String> = self
+            .subscriptions
+            .handles
+            .iter()
+            .filter(|item| item.value().is_live())
+            .map(|item| item.key().clone())
+            .collect();
+        topics.sort();
+        topics
+    }
+
+    // only changes are fanned out, a repeated start just extends the expiry
+    fn set_typing(&self, topic: &str, active: bool) -> anyhow::Result<()> {
+        let was_active = match self.typing.remove(topic) {
+            Some((_, timer)) => {
+                timer.abort();
+                !timer.is_finished()
             }
+            None => false,
+        };
+        let user_name = self.user_name();
+        if active {
+            let topics = self.topics.clone();
+            let (topic_id, user_name) = (topic.to_string(), user_name.clone());
+            let timer = tokio::spawn(async move {
+                tokio::time::sleep(TYPING_EXPIRY).await;
+                let event = Event::Typing(Typing { active: false });
+                // the room may be gone already
+                let _ = topics.notify(&topic_id, &user_name, event);
+            });
+            self.typing.insert(topic.to_string(), timer);
+        }
+        if active != was_active {
+            let event = Event::Typing(Typing { active });
+            self.topics.notify(topic, &user_name, event)?;
         }
         Ok(())
     }
 
-    pub async fn spawn(&mut self, topic: &str, mut msg: Receiver<ServerMessage>) {
-        let sender = self.output_stream.clone();
-        let handle = tokio::spawn(async move {
-            while let Ok(msg) = msg.recv().await {
-                if let Err(e) = sender.send(msg).await {
-                    error!("{e:?}");
+    // deliver to every session of the recipient and echo to our other sessions
+    fn send_direct(&self, data: SendDirect) -> anyhow::Result<()> {
+        let mut targets = self.sessions.find_by_user(&data.to_user);
+        if targets.is_empty() {
+            return Err(ChatError::UserOffline(data.to_user).into());
+        }
+        if data.to_user != self.user_name() {
+            targets.extend(self.sessions.find_by_user(&self.user_name()));
+        }
+
+        let mut msg = ServerMessage::event(
+            "",
+            Event::DirectMessage(DirectMessage {
+                to_user: data.to_user,
+                text: data.text,
+            }),
+        );
+        msg.user_name = self.user_name();
+        msg.session_id = self.id.clone();
+        // like presence, a slow recipient drops it instead of holding up the sender
+        for target in targets.iter().filter(|target| target.id != self.id) {
+            target.push(msg.clone());
+        }
+        Ok(())
+    }
+
+    fn check_subscribed(&self, topic: &str) -> anyhow::Result<()> {
+        match self.subscriptions.handles.get(topic) {
+            Some(sub) if sub.is_live() => Ok(()),
+            _ => Err(ChatError::NotSubscribed(topic.into()).into()),
+        }
+    }
+
+    // forward topic messages to the output stream under the topic slow consumer policy
+    pub async fn spawn(&mut self, topic: &str, sub: Subscription) {
+        let room_gone = Arc::new(AtomicBool::new(false));
+        let forward = Forward {
+            session_id: self.id.clone(),
+            topic: topic.to_string(),
+            sender: self.output_stream.clone(),
+            closed: self.closed.clone(),
+            closed_by_server: self.closed_by_server.clone(),
+            metrics: self.topics.metrics().clone(),
+            policy: sub.slow_consumer,
+            room_gone: room_gone.clone(),
+        };
+        let task = tokio::spawn(forward.run(sub.receiver, sub.sequence));
+        let forwarding = Forwarding { task, room_gone };
+        self.subscriptions
+            .handles
+            .insert(topic.to_string(), forwarding);
+    }
+}
+
+// the forward task of one subscription, holds no Session so dropping the session stops it
+struct Forward {
+    session_id: String,
+    topic: String,
+    sender: Sender<ServerMessage>,
+    closed: Arc<Notify>,
+    closed_by_server: Arc<AtomicBool>,
+    metrics: Arc<SlowConsumerMetrics>,
+    policy: SlowConsumer,
+    room_gone: Arc<AtomicBool>,
+}
+
+impl Forward {
+    async fn run(self, mut receiver: Receiver<ServerMessage>, mut last_sequence: u64) {
+        // messages were skipped, the next sequenced one tells how many
+        let mut lagged = false;
+        loop {
+            let msg = match receiver.recv().await {
+                Ok(msg) => msg,
+                // the topic is dropped only when the room is removed
+                Err(RecvError::Closed) => {
+                    self.room_gone.store(true, Ordering::Release);
                     return;
                 }
+                Err(RecvError::Lagged(skipped)) => {
+                    info!(
+                        "session {} lagged {skipped} messages in {}",
+                        self.session_id, self.topic
+                    );
+                    self.metrics.record_lag(skipped);
+                    match self.policy {
+                        SlowConsumer::DropOldest => {
+                            lagged = true;
+                            continue;
+                        }
+                        _ => return self.disconnect(),
+                    }
+                }
+            };
+            // before the client hears of it, so it can not use the old subscription after
+            if matches!(msg.event, Some(Event::RoomDeleted(_))) {
+                self.room_gone.store(true, Ordering::Release);
             }
+            if msg.sequence > 0 {
+                if lagged && msg.sequence > last_sequence + 1 {
+                    let gap = Gap {
+                        from_sequence: last_sequence + 1,
+                        to_sequence: msg.sequence - 1,
+                    };
+                    if !self
+                        .send(ServerMessage::event(&self.topic, Event::Gap(gap)))
+                        .await
+                    {
+                        return;
+                    }
+                }
+                lagged = false;
+                last_sequence = msg.sequence;
+            }
+            if !self.send(msg).await {
+                return;
+            }
+        }
+    }
+
+    // false when the session is gone or was disconnected
+    async fn send(&self, msg: ServerMessage) -> bool {
+        match self.policy {
+            SlowConsumer::Block(deadline) => match self.sender.send_timeout(msg, deadline).await {
+                Ok(()) => true,
+                Err(SendTimeoutError::Timeout(_)) => {
+                    self.disconnect();
+                    false
+                }
+                Err(SendTimeoutError::Closed(_)) => false,
+            },
+            _ => self.sender.send(msg).await.is_ok(),
+        }
+    }
+
+    fn disconnect(&self) {
+        info!(
+            "disconnect slow session {} in {}",
+            self.session_id, self.topic
+        );
+        self.metrics.record_disconnect();
+        let event = Event::Kicked(Kicked {
+            reason: format!("too slow to receive {}", self.topic),
         });
-        self.subscriptions.insert(topic.to_string(), handle);
+        // the stream is likely full, the notice is best effort
+        let _ = self.sender.try_send(ServerMessage::event("", event));
+        self.closed_by_server.store(true, Ordering::Release);
+        self.closed.notify_one();
     }
 }
 
-impl Drop for Session {
+impl Drop for Subscriptions {
     fn drop(&mut self) {
-        info!("drop session {}", self.user_name);
-        for item in self.subscriptions.iter() {
-            info!("'{}' remove '{}'", self.user_name, item.key());
-            item.value().abort();
-            self.topics.unsubscribe(self.user_name.clone(), item.key())
+        let user_name = self.user_name.read().unwrap().clone();
+        info!("drop session {}", user_name);
+        for item in self.handles.iter() {
+            info!("'{}' remove '{}'", user_name, item.key());
+            item.value().task.abort();
+            // the room was deleted, a topic of the same name is not ours
+            if item.value().is_live() {
+                self.topics.unsubscribe(user_name.clone(), item.key())
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::{AnyNameAuthenticator, DuplicateLogin};
+    use crate::session::hub::{SessionStore, TopicStore};
+    use crate::session::{ChatHooks, Session, SlowConsumer, TopicOptions};
+    use crate::wire::client_message::Message;
+    use crate::wire::{
+        ClientMessage, CreateRoom, DeleteRoom, DirectMessage, ErrorCode, Event, JoinRoom, Login,
+        PresenceStatus, Resume, SendDirect, ServerMessage, SetPresence, SubscribePresence, Typing,
+    };
+    use std::sync::atomic::Ordering;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::sync::mpsc::{channel, Receiver, Sender};
+    use tokio::task::JoinHandle;
+
+    fn start(
+        id: &str,
+        sessions: Arc<SessionStore>,
+    ) -> (
+        Sender<ClientMessage>,
+        Receiver<ServerMessage>,
+        JoinHandle<anyhow::Result<()>>,
+    ) {
+        start_in(id, sessions, Arc::new(TopicStore::new()))
+    }
+
+    // sessions sharing `topics`
+    fn start_in(
+        id: &str,
+        sessions: Arc<SessionStore>,
+        topics: Arc<TopicStore>,
+    ) -> (
+        Sender<ClientMessage>,
+        Receiver<ServerMessage>,
+        JoinHandle<anyhow::Result<()>>,
+    ) {
+        let (client_tx, client_rx) = channel(4);
+        let (server_tx, server_rx) = channel(4);
+        let mut sess = Session::new(id.into(), sessions.clone(), topics, server_tx);
+        sessions.add(sess.clone());
+        let task = tokio::spawn(async move { sess.run(client_rx).await });
+        (client_tx, server_rx, task)
+    }
+
+    fn command(request_id: &str, topic: &str, message: Message) -> ClientMessage {
+        ClientMessage {
+            topic: topic.into(),
+            message: Some(message),
+            request_id: request_id.into(),
+            ..Default::default()
+        }
+    }
+
+    fn login(name: &str) -> Message {
+        Message::Login(Login {
+            name: name.into(),
+            ..Default::default()
+        })
+    }
+
+    // skip broadcasts until the reply of a command
+    async fn reply(server_rx: &mut Receiver<ServerMessage>) -> ServerMessage {
+        loop {
+            let msg = server_rx.recv().await.unwrap();
+            if !msg.request_id.is_empty() {
+                return msg;
+            }
+        }
+    }
+
+    fn error_code(msg: &ServerMessage) -> i32 {
+        match &msg.event {
+            Some(Event::Error(e)) => e.code,
+            event => panic!("unexpected {event:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn session_reply_request_id() {
+        let (client_tx, mut server_rx, _) = start("s1", Arc::new(SessionStore::new()));
+
+        let send = Message::SendMessage("hi".into());
+        client_tx.send(command("1", "room", send)).await.unwrap();
+        let msg = reply(&mut server_rx).await;
+        assert_eq!(msg.request_id, "1");
+        assert_eq!(error_code(&msg), ErrorCode::Unauthenticated as i32);
+
+        client_tx
+            .send(command("2", "", login("bob")))
+            .await
+            .unwrap();
+        let msg = reply(&mut server_rx).await;
+        assert_eq!(msg.request_id, "2");
+        assert!(matches!(msg.event, Some(Event::Ack(_))));
+
+        let send = Message::SendMessage("hi".into());
+        client_tx.send(command("3", "room", send)).await.unwrap();
+        let msg = reply(&mut server_rx).await;
+        assert_eq!(error_code(&msg), ErrorCode::NotSubscribed as i32);
+
+        let join = Message::JoinRoom(JoinRoom {});
+        client_tx.send(command("4", "room", join)).await.unwrap();
+        let msg = reply(&mut server_rx).await;
+        assert_eq!(error_code(&msg), ErrorCode::TopicNotFound as i32);
+
+        let create = Message::CreateRoom(CreateRoom::default());
+        client_tx.send(command("4", "room", create)).await.unwrap();
+        let msg = reply(&mut server_rx).await;
+        assert_eq!(msg.request_id, "4");
+        assert!(matches!(msg.event, Some(Event::Ack(_))));
+
+        client_tx
+            .send(command("5", "", login("alice")))
+            .await
+            .unwrap();
+        let msg = reply(&mut server_rx).await;
+        assert_eq!(error_code(&msg), ErrorCode::AlreadyLoggedIn as i32);
+
+        // no command, like the removed join_user of old clients
+        let empty = ClientMessage {
+            request_id: "6".into(),
+            ..Default::default()
+        };
+        client_tx.send(empty).await.unwrap();
+        let msg = reply(&mut server_rx).await;
+        assert_eq!(msg.request_id, "6");
+        assert_eq!(error_code(&msg), ErrorCode::InvalidMessage as i32);
+    }
+
+    #[tokio::test]
+    async fn duplicate_login() {
+        let sessions = Arc::new(SessionStore::with_auth(
+            Arc::new(AnyNameAuthenticator),
+            DuplicateLogin::Reject,
+        ));
+        let (tx1, mut rx1, _) = start("s1", sessions.clone());
+        let (tx2, mut rx2, _) = start("s2", sessions.clone());
+        tx1.send(command("1", "", login("bob"))).await.unwrap();
+        assert!(matches!(reply(&mut rx1).await.event, Some(Event::Ack(_))));
+        tx2.send(command("1", "", login("bob"))).await.unwrap();
+        assert_eq!(
+            error_code(&reply(&mut rx2).await),
+            ErrorCode::AlreadyLoggedIn as i32
+        );
+
+        let sessions = Arc::new(SessionStore::with_auth(
+            Arc::new(AnyNameAuthenticator),
+            DuplicateLogin::KickOld,
+        ));
+        let (tx1, mut rx1, task1) = start("s1", sessions.clone());
+        let (tx2, mut rx2, _) = start("s2", sessions.clone());
+        tx1.send(command("1", "", login("bob"))).await.unwrap();
+        assert!(matches!(reply(&mut rx1).await.event, Some(Event::Ack(_))));
+        tx2.send(command("1", "", login("bob"))).await.unwrap();
+        assert!(matches!(reply(&mut rx2).await.event, Some(Event::Ack(_))));
+        assert!(matches!(
+            rx1.recv().await.unwrap().event,
+            Some(Event::Kicked(_))
+        ));
+        task1.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn deleted_room_recreated() {
+        let sessions = Arc::new(SessionStore::new());
+        let topics = Arc::new(TopicStore::new());
+        let (alice, mut alice_rx, _) = start_in("a1", sessions.clone(), topics.clone());
+        let (bob1, mut bob1_rx, bob1_task) = start_in("b1", sessions.clone(), topics.clone());
+        let (bob2, mut bob2_rx, _) = start_in("b2", sessions.clone(), topics.clone());
+        for (tx, rx, name) in [
+            (&alice, &mut alice_rx, "alice"),
+            (&bob1, &mut bob1_rx, "bob"),
+            (&bob2, &mut bob2_rx, "bob"),
+        ] {
+            tx.send(command("1", "", login(name))).await.unwrap();
+            assert!(matches!(reply(rx).await.event, Some(Event::Ack(_))));
+        }
+        let create = || Message::CreateRoom(CreateRoom::default());
+        alice.send(command("2", "room", create())).await.unwrap();
+        reply(&mut alice_rx).await;
+        let join = || Message::JoinRoom(JoinRoom {});
+        bob1.send(command("2", "room", join())).await.unwrap();
+        reply(&mut bob1_rx).await;
+        let delete = Message::DeleteRoom(DeleteRoom {});
+        alice.send(command("3", "room", delete)).await.unwrap();
+        reply(&mut alice_rx).await;
+        while !matches!(
+            bob1_rx.recv().await.unwrap().event,
+            Some(Event::RoomDeleted(_))
+        ) {}
+
+        // a new room of the same name, bob1 never joined it
+        alice.send(command("4", "room", create())).await.unwrap();
+        reply(&mut alice_rx).await;
+        bob2.send(command("2", "room", join())).await.unwrap();
+        reply(&mut bob2_rx).await;
+        let send = Message::SendMessage("hi".into());
+        bob1.send(command("3", "room", send)).await.unwrap();
+        assert_eq!(
+            error_code(&reply(&mut bob1_rx).await),
+            ErrorCode::NotSubscribed as i32
+        );
+
+        // bob1 going away leaves bob2 in the new room
+        drop(bob1);
+        bob1_task.await.unwrap().unwrap();
+        sessions.remove("b1".into());
+        assert_eq!(
+            topics.members("room").unwrap(),
+            vec!["alice".to_string(), "bob".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn kicked_not_resumable() {
+        let sessions =
+            SessionStore::with_auth(Arc::new(AnyNameAuthenticator), DuplicateLogin::KickOld)
+                .with_resume_grace(std::time::Duration::from_secs(60));
+        let sessions = Arc::new(sessions);
+        let (tx1, mut rx1, task1) = start("s1", sessions.clone());
+        tx1.send(command("1", "", login("bob"))).await.unwrap();
+        let session_token = match reply(&mut rx1).await.event {
+            Some(Event::Ack(ack)) => ack.session_token,
+            event => panic!("unexpected {event:?}"),
+        };
+        let (tx2, mut rx2, _) = start("s2", sessions.clone());
+        tx2.send(command("1", "", login("bob"))).await.unwrap();
+        assert!(matches!(reply(&mut rx2).await.event, Some(Event::Ack(_))));
+        task1.await.unwrap().unwrap();
+        // not kept for the grace period
+        sessions.release("s1".into());
+        assert_eq!(sessions.find_by_user("bob").len(), 1);
+
+        let (tx3, mut rx3, _) = start("s3", sessions.clone());
+        let resume = Message::Resume(Resume {
+            session_token,
+            ..Default::default()
+        });
+        tx3.send(command("1", "", resume)).await.unwrap();
+        assert_eq!(
+            error_code(&reply(&mut rx3).await),
+            ErrorCode::Unauthenticated as i32
+        );
+        assert_eq!(sessions.find_by_user("bob").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn resume_authenticated() {
+        let sessions =
+            SessionStore::with_auth(Arc::new(AnyNameAuthenticator), DuplicateLogin::Reject)
+                .with_resume_grace(std::time::Duration::from_secs(60));
+        let sessions = Arc::new(sessions);
+        let (alice, mut alice_rx, task) = start("a1", sessions.clone());
+        alice.send(command("1", "", login("alice"))).await.unwrap();
+        let session_token = match reply(&mut alice_rx).await.event {
+            Some(Event::Ack(ack)) => ack.session_token,
+            event => panic!("unexpected {event:?}"),
+        };
+        drop(alice);
+        task.await.unwrap().unwrap();
+        sessions.release("a1".into());
+
+        // authenticated by their transports, the detached session is no duplicate
+        let session = |id: &str, user_name: &str| {
+            let (client_tx, client_rx) = channel(4);
+            let (server_tx, server_rx) = channel(4);
+            let topics = Arc::new(TopicStore::new());
+            let mut sess = Session::new(id.into(), sessions.clone(), topics, server_tx);
+            sessions
+                .add_with_user(sess.clone(), Some(user_name.into()))
+                .unwrap();
+            tokio::spawn(async move { sess.run(client_rx).await });
+            (client_tx, server_rx)
+        };
+        let (bob, mut bob_rx) = session("b1", "bob");
+        let (alice, mut alice_rx) = session("a2", "alice");
+
+        let resume = || {
+            Message::Resume(Resume {
+                session_token: session_token.clone(),
+                ..Default::default()
+            })
+        };
+        bob.send(command("1", "", resume())).await.unwrap();
+        assert_eq!(
+            error_code(&reply(&mut bob_rx).await),
+            ErrorCode::Unauthenticated as i32
+        );
+        alice.send(command("1", "", resume())).await.unwrap();
+        assert!(matches!(
+            reply(&mut alice_rx).await.event,
+            Some(Event::Ack(_))
+        ));
+        assert_eq!(sessions.find_by_user("alice").len(), 1);
+        assert_eq!(
+            sessions.presence("alice").status,
+            PresenceStatus::Online as i32
+        );
+    }
+
+    // records the calls, rejects messages saying "spam"
+    #[derive(Default)]
+    struct Recorder {
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl ChatHooks for Recorder {
+        fn on_connect(&self, session_id: &str) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("connect {session_id}"));
+        }
+
+        fn on_login(&self, session_id: &str, user_name: &str) {
+            let call = format!("login {session_id} {user_name}");
+            self.calls.lock().unwrap().push(call);
+        }
+
+        fn on_message(&self, topic: &str, msg: &ServerMessage) -> anyhow::Result<()> {
+            match &msg.event {
+                Some(Event::ChatMessage(text)) if text == "spam" => anyhow::bail!("no spam"),
+                _ => {
+                    let call = format!("message {topic} {}", msg.user_name);
+                    self.calls.lock().unwrap().push(call);
+                    Ok(())
+                }
+            }
+        }
+
+        fn on_disconnect(&self, session_id: &str, user_name: &str) {
+            let call = format!("disconnect {session_id} {user_name}");
+            self.calls.lock().unwrap().push(call);
+        }
+    }
+
+    #[tokio::test]
+    async fn hooks() {
+        let hooks = Arc::new(Recorder::default());
+        let sessions = Arc::new(SessionStore::new().with_hooks(hooks.clone()));
+        let (bob, mut bob_rx, _) = start("b1", sessions.clone());
+        bob.send(command("1", "", login("bob"))).await.unwrap();
+        reply(&mut bob_rx).await;
+        let create = Message::CreateRoom(CreateRoom::default());
+        bob.send(command("2", "room", create)).await.unwrap();
+        reply(&mut bob_rx).await;
+        for text in ["hi", "spam"] {
+            let send = Message::SendMessage(text.into());
+            bob.send(command("3", "room", send)).await.unwrap();
+        }
+        assert!(matches!(
+            reply(&mut bob_rx).await.event,
+            Some(Event::Ack(_))
+        ));
+        assert!(matches!(
+            reply(&mut bob_rx).await.event,
+            Some(Event::Error(_))
+        ));
+        sessions.remove("b1".into());
+        assert_eq!(
+            *hooks.calls.lock().unwrap(),
+            vec![
+                "connect b1",
+                "login b1 bob",
+                "message room bob",
+                "disconnect b1 bob"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn direct_message() {
+        let sessions = Arc::new(SessionStore::new());
+        let (alice1, mut alice1_rx, _) = start("a1", sessions.clone());
+        let (alice2, mut alice2_rx, _) = start("a2", sessions.clone());
+        let (bob, mut bob_rx, _) = start("b1", sessions.clone());
+        for (tx, rx, name) in [
+            (&alice1, &mut alice1_rx, "alice"),
+            (&alice2, &mut alice2_rx, "alice"),
+            (&bob, &mut bob_rx, "bob"),
+        ] {
+            tx.send(command("1", "", login(name))).await.unwrap();
+            assert!(matches!(reply(rx).await.event, Some(Event::Ack(_))));
+        }
+
+        let direct = |to_user: &str| {
+            Message::SendDirect(SendDirect {
+                to_user: to_user.into(),
+                text: "hi".into(),
+            })
+        };
+        alice1.send(command("2", "", direct("bob"))).await.unwrap();
+        assert!(matches!(
+            reply(&mut alice1_rx).await.event,
+            Some(Event::Ack(_))
+        ));
+        let expected = Some(Event::DirectMessage(DirectMessage {
+            to_user: "bob".into(),
+            text: "hi".into(),
+        }));
+        for rx in [&mut bob_rx, &mut alice2_rx] {
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(msg.event, expected);
+            assert_eq!(msg.user_name, "alice");
+            assert_eq!(msg.session_id, "a1");
+        }
+
+        alice1
+            .send(command("3", "", direct("carol")))
+            .await
+            .unwrap();
+        assert_eq!(
+            error_code(&reply(&mut alice1_rx).await),
+            ErrorCode::UserOffline as i32
+        );
+        assert!(alice1_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn direct_message_slow_recipient() {
+        let sessions = Arc::new(SessionStore::new());
+        let (alice, mut alice_rx, _) = start("a1", sessions.clone());
+        // bob never reads
+        let (bob, mut bob_rx, _) = start("b1", sessions.clone());
+        for (tx, rx, name) in [(&alice, &mut alice_rx, "alice"), (&bob, &mut bob_rx, "bob")] {
+            tx.send(command("1", "", login(name))).await.unwrap();
+            reply(rx).await;
+        }
+        let sent = async {
+            for _ in 0..10 {
+                let direct = Message::SendDirect(SendDirect {
+                    to_user: "bob".into(),
+                    text: "hi".into(),
+                });
+                alice.send(command("2", "", direct)).await.unwrap();
+                assert!(matches!(
+                    reply(&mut alice_rx).await.event,
+                    Some(Event::Ack(_))
+                ));
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(5), sent)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn presence() {
+        let sessions = Arc::new(SessionStore::new());
+        let (bob, mut bob_rx, _) = start("b1", sessions.clone());
+        bob.send(command("1", "", login("bob"))).await.unwrap();
+        reply(&mut bob_rx).await;
+
+        let watch = Message::SubscribePresence(SubscribePresence {
+            users: vec!["alice".into()],
+        });
+        bob.send(command("2", "", watch)).await.unwrap();
+        let status = |msg: ServerMessage| match msg.event {
+            Some(Event::Presence(p)) => (msg.user_name, p.status),
+            event => panic!("unexpected {event:?}"),
+        };
+        let offline = ("alice".to_string(), PresenceStatus::Offline as i32);
+        assert_eq!(status(bob_rx.recv().await.unwrap()), offline);
+        reply(&mut bob_rx).await;
+
+        let (alice, mut alice_rx, _) = start("a1", sessions.clone());
+        alice.send(command("1", "", login("alice"))).await.unwrap();
+        reply(&mut alice_rx).await;
+        let online = ("alice".to_string(), PresenceStatus::Online as i32);
+        assert_eq!(status(bob_rx.recv().await.unwrap()), online);
+
+        let idle = Message::SetPresence(SetPresence {
+            status: PresenceStatus::Idle as i32,
+        });
+        alice.send(command("2", "", idle)).await.unwrap();
+        reply(&mut alice_rx).await;
+        let idle = ("alice".to_string(), PresenceStatus::Idle as i32);
+        assert_eq!(status(bob_rx.recv().await.unwrap()), idle);
+
+        sessions.remove("a1".into());
+        assert_eq!(status(bob_rx.recv().await.unwrap()), offline);
+    }
+
+    #[tokio::test]
+    async fn typing() {
+        let sessions = Arc::new(SessionStore::new());
+        let topics = Arc::new(TopicStore::new());
+        let (alice, mut alice_rx, _) = start_in("a1", sessions.clone(), topics.clone());
+        let (bob, mut bob_rx, _) = start_in("b1", sessions.clone(), topics.clone());
+        alice.send(command("1", "", login("alice"))).await.unwrap();
+        reply(&mut alice_rx).await;
+        bob.send(command("1", "", login("bob"))).await.unwrap();
+        reply(&mut bob_rx).await;
+        let create = Message::CreateRoom(CreateRoom::default());
+        alice.send(command("2", "room", create)).await.unwrap();
+        reply(&mut alice_rx).await;
+        bob.send(command("2", "room", Message::JoinRoom(JoinRoom {})))
+            .await
+            .unwrap();
+        reply(&mut bob_rx).await;
+
+        let typing = |active| Message::Typing(Typing { active });
+        alice
+            .send(command("3", "room", typing(true)))
+            .await
+            .unwrap();
+        reply(&mut alice_rx).await;
+        // a refresh is not fanned out again
+        alice
+            .send(command("4", "room", typing(true)))
+            .await
+            .unwrap();
+        reply(&mut alice_rx).await;
+        alice
+            .send(command("5", "room", Message::SendMessage("hi".into())))
+            .await
+            .unwrap();
+        let ack = reply(&mut alice_rx).await;
+
+        // bob's forward task may still be behind alice's ack
+        let mut events = vec![];
+        while !matches!(events.last(), Some((_, Some(Event::ChatMessage(_))))) {
+            let msg = bob_rx.recv().await.unwrap();
+            if msg.user_name == "alice" {
+                events.push((msg.sequence, msg.event));
+            }
+        }
+        assert_eq!(
+            events,
+            vec![
+                (0, Some(Event::Typing(Typing { active: true }))),
+                (0, Some(Event::Typing(Typing { active: false }))),
+                (1, Some(Event::ChatMessage("hi".into()))),
+            ]
+        );
+        // typing takes no sequence
+        assert!(matches!(ack.event, Some(Event::Ack(a)) if a.sequence == 1));
+    }
+
+    // bob in a room buffering 2 messages, with an output stream of 1
+    async fn slow_session(
+        policy: SlowConsumer,
+    ) -> (Arc<TopicStore>, Session, Receiver<ServerMessage>) {
+        let topics = Arc::new(TopicStore::with_options(TopicOptions {
+            channel_size: 2,
+            slow_consumer: policy,
+            ..Default::default()
+        }));
+        topics
+            .create("room", "alice".into(), &CreateRoom::default())
+            .unwrap();
+        let (server_tx, server_rx) = channel(1);
+        let mut sess = Session::new(
+            "s1".into(),
+            Arc::new(SessionStore::new()),
+            topics.clone(),
+            server_tx,
+        );
+        sess.set_user_name("bob".into());
+        sess.join("room").await.unwrap();
+        (topics, sess, server_rx)
+    }
+
+    fn publish(topics: &TopicStore, count: usize) {
+        for i in 1..=count {
+            let msg = ServerMessage {
+                event: Some(Event::ChatMessage(format!("msg {i}"))),
+                ..Default::default()
+            };
+            topics.send_message("room", msg).unwrap();
+        }
+    }
+
+    // read the output stream until the forward task closes the session
+    async fn until_disconnected(topics: &TopicStore, server_rx: &mut Receiver<ServerMessage>) {
+        while topics.metrics().stats().disconnects == 0 {
+            server_rx.recv().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_consumer_gap() {
+        let (topics, _sess, mut server_rx) = slow_session(SlowConsumer::DropOldest).await;
+        // nobody reads the output stream while the room is busy
+        publish(&topics, 10);
+
+        // every sequence arrives or is covered by a gap
+        let mut covered = vec![];
+        while covered.last() != Some(&10) {
+            match server_rx.recv().await.unwrap() {
+                ServerMessage {
+                    event: Some(Event::Gap(gap)),
+                    ..
+                } => covered.extend(gap.from_sequence..=gap.to_sequence),
+                msg if msg.sequence > 0 => covered.push(msg.sequence),
+                _ => {}
+            }
+        }
+        assert_eq!(covered, (1..=10).collect::<Vec<_>>());
+        let stats = topics.metrics().stats();
+        assert_eq!(stats.lagged, 1);
+        assert!(stats.skipped > 0);
+        assert_eq!(stats.disconnects, 0);
+    }
+
+    #[tokio::test]
+    async fn slow_consumer_disconnect() {
+        let (topics, sess, mut server_rx) = slow_session(SlowConsumer::Disconnect).await;
+        publish(&topics, 10);
+        until_disconnected(&topics, &mut server_rx).await;
+        let stats = topics.metrics().stats();
+        assert_eq!((stats.lagged, stats.disconnects), (1, 1));
+        assert!(sess.closed_by_server.load(Ordering::Acquire));
+    }
+
+    #[tokio::test]
+    async fn slow_consumer_block() {
+        // within the room buffer, closed once the deadline passes
+        let deadline = Duration::from_millis(20);
+        let (topics, sess, mut server_rx) = slow_session(SlowConsumer::Block(deadline)).await;
+        // with the join notice of bob, the output stream takes only one
+        publish(&topics, 1);
+        tokio::time::sleep(deadline * 5).await;
+        until_disconnected(&topics, &mut server_rx).await;
+        let stats = topics.metrics().stats();
+        assert_eq!((stats.lagged, stats.disconnects), (0, 1));
+        assert!(sess.closed_by_server.load(Ordering::Acquire));
+
+        // further behind than the room buffer, closed like Disconnect however long the deadline
+        let deadline = Duration::from_secs(60);
+        let (topics, sess, mut server_rx) = slow_session(SlowConsumer::Block(deadline)).await;
+        publish(&topics, 10);
+        until_disconnected(&topics, &mut server_rx).await;
+        let stats = topics.metrics().stats();
+        assert_eq!((stats.lagged, stats.disconnects), (1, 1));
+        assert!(sess.closed_by_server.load(Ordering::Acquire));
+    }
+
+    #[tokio::test]
+    async fn resume() {
+        let sessions =
+            Arc::new(SessionStore::new().with_resume_grace(std::time::Duration::from_secs(60)));
+        let topics = Arc::new(TopicStore::new());
+        let (alice, mut alice_rx, task) = start_in("a1", sessions.clone(), topics.clone());
+        alice.send(command("1", "", login("alice"))).await.unwrap();
+        let session_token = match reply(&mut alice_rx).await.event {
+            Some(Event::Ack(ack)) => ack.session_token,
+            event => panic!("unexpected {event:?}"),
+        };
+        assert!(!session_token.is_empty());
+        let create = Message::CreateRoom(CreateRoom::default());
+        alice.send(command("2", "room", create)).await.unwrap();
+        reply(&mut alice_rx).await;
+        for i in 1..=3 {
+            let send = Message::SendMessage(format!("msg {i}"));
+            alice.send(command("3", "room", send)).await.unwrap();
+            reply(&mut alice_rx).await;
+        }
+
+        // transport lost, the room is kept for the detached session
+        drop(alice);
+        task.await.unwrap().unwrap();
+        sessions.release("a1".into());
+        assert_eq!(topics.members("room").unwrap(), vec!["alice".to_string()]);
+
+        let (alice, mut alice_rx, _) = start_in("a2", sessions.clone(), topics.clone());
+        let resume = Message::Resume(Resume {
+            session_token,
+            last_seen: [("room".to_string(), 1)].into(),
+        });
+        alice.send(command("1", "", resume)).await.unwrap();
+        let mut replayed = vec![];
+        let ack = loop {
+            let msg = alice_rx.recv().await.unwrap();
+            match msg.event {
+                Some(Event::ChatMessage(_)) => replayed.push(msg.sequence),
+                Some(Event::Ack(ack)) => break ack,
+                event => panic!("unexpected {event:?}"),
+            }
+        };
+        assert_eq!(replayed, vec![2, 3]);
+        assert!(!ack.session_token.is_empty());
+
+        // subscribed without joining again
+        let send = Message::SendMessage("back".into());
+        alice.send(command("2", "room", send)).await.unwrap();
+        assert!(matches!(
+            reply(&mut alice_rx).await.event,
+            Some(Event::Ack(ack)) if ack.sequence == 4
+        ));
+        assert_eq!(topics.members("room").unwrap(), vec!["alice".to_string()]);
+    }
+}
@@ -1,55 +1,217 @@
 // 单个 topic 处理
 
-use crate::wire::ServerMessage;
-use dashmap::DashSet;
+use crate::storage::MessageStore;
+use crate::utils::timestamp_millis;
+use crate::wire::{Event, MemberJoined, MemberLeft, ServerMessage};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::broadcast;
 use tokio::sync::broadcast::{Receiver, Sender};
 use tracing::info;
 
 const SUBSCRIPT_SIZE: usize = 16;
 
+#[derive(Clone, Debug)]
+pub struct TopicOptions {
+    // max messages kept per topic by the in-memory store, 0 disables history
+    pub history_size: usize,
+    // messages replayed to a session when it subscribes
+    pub replay_size: usize,
+    // messages buffered per topic for subscribers which fall behind
+    pub channel_size: usize,
+    // default of new topics
+    pub slow_consumer: SlowConsumer,
+    // message ids remembered per topic to drop retried sends
+    pub dedup_window: usize,
+}
+
+impl Default for TopicOptions {
+    fn default() -> Self {
+        Self {
+            history_size: 100,
+            replay_size: 0,
+            channel_size: SUBSCRIPT_SIZE,
+            slow_consumer: SlowConsumer::default(),
+            dedup_window: 1000,
+        }
+    }
+}
+
+// what happens to a subscriber which cannot keep up with the topic
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SlowConsumer {
+    // skip the oldest messages and send a gap with the missed sequences
+    #[default]
+    DropOldest,
+    // close the session as soon as it misses a message
+    Disconnect,
+    // wait up to the deadline for the session to take each message, then close it,
+    // the topic still buffers only `channel_size` messages for it, a session that
+    // falls further behind is closed like Disconnect
+    Block(Duration),
+}
+
+// a new subscriber of a topic
+pub struct Subscription {
+    pub receiver: Receiver<ServerMessage>,
+    // the last messages, oldest first
+    pub history: Vec<ServerMessage>,
+    // last sequence published before subscribing
+    pub sequence: u64,
+    pub slow_consumer: SlowConsumer,
+}
+
 // global topic store
 
 #[derive(Clone)]
 pub struct Topic {
     pub id: String,
-    pub subscribes: DashSet<String>,
+    // key: user_name, value: sessions of the user subscribed
+    pub subscribes: HashMap<String, usize>,
+    // user who created the room, empty for topics stored before rooms were
+    pub owner: String,
+    // kept when the last member leaves
+    pub persistent: bool,
+    pub slow_consumer: SlowConsumer,
     sequence: u64,
+    history_size: usize,
+    // key: (user_name, message_id), value: sequence, the oldest are forgotten first
+    recent: HashMap<(String, String), u64>,
+    recent_order: VecDeque<(String, String)>,
+    dedup_window: usize,
     input_stream: Sender<ServerMessage>,
+    store: Arc<dyn MessageStore>,
 }
 
 impl Topic {
-    pub fn new(id: String) -> Topic {
-        let (tx, _) = broadcast::channel(SUBSCRIPT_SIZE);
+    // `sequence` is the last sequence already stored for this topic
+    pub fn new(
+        id: String,
+        sequence: u64,
+        store: Arc<dyn MessageStore>,
+        options: &TopicOptions,
+    ) -> Topic {
+        let (tx, _) = broadcast::channel(options.channel_size.max(1));
         Topic {
             id,
-            sequence: 0,
+            sequence,
             input_stream: tx,
-            subscribes: DashSet::new(),
+            subscribes: HashMap::new(),
+            history_size: options.history_size,
+            owner: String::new(),
+            persistent: false,
+            slow_consumer: options.slow_consumer,
+            recent: HashMap::new(),
+            recent_order: VecDeque::new(),
+            dedup_window: options.dedup_window,
+            store,
         }
     }
 
+    // members are told when the first session of a user joins
     pub fn subscribe(&mut self, user_name: String) -> Receiver<ServerMessage> {
-        self.subscribes.insert(user_name);
-        self.input_stream.subscribe()
+        let receiver = self.input_stream.subscribe();
+        let count = self.subscribes.entry(user_name.clone()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            self.notify(&user_name, Event::MemberJoined(MemberJoined {}));
+        }
+        receiver
     }
 
+    // and when the last one leaves, returns the users left
     pub fn unsubscribe(&mut self, user_name: String) -> usize {
-        self.subscribes.remove(&user_name);
+        if let Some(count) = self.subscribes.get_mut(&user_name) {
+            *count -= 1;
+            if *count == 0 {
+                self.subscribes.remove(&user_name);
+                self.notify(&user_name, Event::MemberLeft(MemberLeft {}));
+            }
+        }
         self.subscribes.len()
     }
 
-    pub fn publish(&mut self, msg: String) -> anyhow::Result<()> {
-        info!("publish message: {msg}");
-        self.sequence += 1;
-        let msg = ServerMessage {
-            sequence: self.sequence,
-            topic: self.id.clone(),
-            message: Some(msg),
+    // broadcast an event about `user_name` without a sequence, not kept in history
+    pub fn notify(&self, user_name: &str, event: Event) {
+        let mut msg = ServerMessage::event(&self.id, event);
+        msg.user_name = user_name.to_string();
+        // no receiver is not an error for notifications
+        let _ = self.input_stream.send(msg);
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    // messages before `before_sequence` (0 means latest), oldest first
+    pub fn history(
+        &self,
+        before_sequence: u64,
+        limit: usize,
+    ) -> anyhow::Result<Vec<ServerMessage>> {
+        let end = match before_sequence {
+            0 => self.sequence + 1,
+            seq => seq.min(self.sequence + 1),
+        };
+        // never more than the store keeps, 0 asks for all of it
+        let limit = match limit {
+            0 => self.history_size,
+            limit => limit.min(self.history_size),
         };
-        self.input_stream.send(msg)?;
+        // sequences are contiguous, so the range covers at most `limit` messages
+        let start = end.saturating_sub(limit as u64);
+        self.store.range(&self.id, start..end)
+    }
+
+    // assign sequence, topic and timestamp, the sender fields are kept, returns the sequence
+    pub fn publish(&mut self, mut msg: ServerMessage) -> anyhow::Result<u64> {
+        info!("publish message: {:?} from {}", msg.event, msg.user_name);
+        msg.sequence = self.sequence + 1;
+        msg.topic = self.id.clone();
+        msg.timestamp = timestamp_millis();
+        self.store.append(&msg)?;
+        self.sequence = msg.sequence;
+        // stored already, having no live receiver is fine
+        let _ = self.input_stream.send(msg);
+        Ok(self.sequence)
+    }
+
+    // publish unless the sender published `message_id` recently, then its sequence is returned
+    pub fn publish_once(&mut self, msg: ServerMessage) -> anyhow::Result<u64> {
+        if msg.message_id.is_empty() || self.dedup_window == 0 {
+            return self.publish(msg);
+        }
+        let key = (msg.user_name.clone(), msg.message_id.clone());
+        if let Some(sequence) = self.recent.get(&key) {
+            info!("duplicate message {:?} in {}", key, self.id);
+            return Ok(*sequence);
+        }
+        let sequence = self.publish(msg)?;
+        self.remember(key, sequence);
+        Ok(sequence)
+    }
+
+    // after a restart, from the last `dedup_window` messages the store still has
+    pub fn restore_recent(&mut self) -> anyhow::Result<()> {
+        let start = self.sequence.saturating_sub(self.dedup_window as u64) + 1;
+        for msg in self.store.range(&self.id, start..self.sequence + 1)? {
+            if !msg.message_id.is_empty() {
+                self.remember((msg.user_name, msg.message_id), msg.sequence);
+            }
+        }
         Ok(())
     }
+
+    fn remember(&mut self, key: (String, String), sequence: u64) {
+        self.recent.insert(key.clone(), sequence);
+        self.recent_order.push_back(key);
+        if self.recent_order.len() > self.dedup_window {
+            if let Some(oldest) = self.recent_order.pop_front() {
+                self.recent.remove(&oldest);
+            }
+        }
+    }
 }
 
 impl Drop for Topic {
@@ -0,0 +1,409 @@
+// 文件存储，每个 topic 一个 append-only 日志，每行一条 json 消息
+// room 的 owner 和 persistent 存在同名的 .room 文件
+// 最近 capacity 条消息留在内存里，读不碰磁盘；文件由后台线程写，日志超过两倍 capacity 时压缩
+// 后台写失败后，之后的写入都返回错误
+
+use crate::storage::{MessageStore, StoredRoom};
+use crate::wire::ServerMessage;
+use dashmap::DashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use tracing::{error, info};
+
+const LOG_PREFIX: &str = "topic-";
+const LOG_EXTENSION: &str = "log";
+const ROOM_EXTENSION: &str = "room";
+const TMP_EXTENSION: &str = "tmp";
+
+#[derive(Default)]
+struct TopicLog {
+    last_sequence: u64,
+    // latest messages, all `range` can return
+    messages: VecDeque<ServerMessage>,
+    // lines in the file
+    lines: usize,
+}
+
+// done in order by the writer thread
+enum FileWrite {
+    Append(PathBuf, Vec<u8>),
+    Replace(PathBuf, Vec<u8>),
+    Remove(PathBuf),
+}
+
+pub struct FileStore {
+    dir: PathBuf,
+    // max messages kept per topic, the log keeps at least the last one for its sequence
+    capacity: usize,
+    // key: topic_id, value: latest messages
+    logs: DashMap<String, TopicLog>,
+    // key: topic_id, value: saved room
+    rooms: DashMap<String, StoredRoom>,
+    writes: Option<Sender<FileWrite>>,
+    writer: Option<JoinHandle<()>>,
+    // set by the writer, what is in memory may not be on disk anymore
+    failed: Arc<AtomicBool>,
+}
+
+impl FileStore {
+    // open the log directory, creating it when missing
+    pub fn open(dir: impl AsRef<Path>, capacity: usize) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let logs = DashMap::new();
+        let rooms = DashMap::new();
+
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let Some(topic_id) = path.file_stem().and_then(|s| decode_name(s.to_str()?)) else {
+                continue;
+            };
+            let extension = path.extension().and_then(|ext| ext.to_str());
+            if extension == Some(ROOM_EXTENSION) {
+                let room = serde_json::from_slice(&fs::read(&path)?)?;
+                rooms.insert(topic_id, room);
+            } else if extension == Some(LOG_EXTENSION) {
+                let messages = read_log(&path)?;
+                let last = messages.last().cloned();
+                let mut log = TopicLog::default();
+                for msg in messages {
+                    log.last_sequence = msg.sequence;
+                    log.lines += 1;
+                    push_bounded(&mut log.messages, msg, capacity);
+                }
+                info!("load topic log {topic_id:?} sequence {}", log.last_sequence);
+                // logs written before they were bounded
+                if log.lines > 2 * capacity.max(1) {
+                    let (content, lines) = match log.messages.is_empty() {
+                        true => compacted(&last)?,
+                        false => compacted(&log.messages)?,
+                    };
+                    replace(&path, &content)?;
+                    log.lines = lines;
+                }
+                logs.insert(topic_id, log);
+            }
+        }
+
+        let (writes, receiver) = channel();
+        let failed = Arc::new(AtomicBool::new(false));
+        let writer_failed = failed.clone();
+        let writer = std::thread::Builder::new()
+            .name("file-store".into())
+            .spawn(move || write_files(receiver, &writer_failed))?;
+        Ok(FileStore {
+            dir,
+            capacity,
+            logs,
+            rooms,
+            writes: Some(writes),
+            writer: Some(writer),
+            failed,
+        })
+    }
+
+    fn path(&self, topic_id: &str, extension: &str) -> PathBuf {
+        self.dir
+            .join(encode_name(topic_id))
+            .with_extension(extension)
+    }
+
+    fn write(&self, write: FileWrite) -> anyhow::Result<()> {
+        if self.failed.load(Ordering::Acquire) {
+            anyhow::bail!("file store {:?} failed to write, see the log", self.dir);
+        }
+        let writes = self.writes.as_ref().expect("writer runs until drop");
+        writes
+            .send(write)
+            .map_err(|_| anyhow::anyhow!("file store writer stopped"))
+    }
+}
+
+impl Drop for FileStore {
+    // pending writes are done before the store is gone
+    fn drop(&mut self) {
+        self.writes.take();
+        if let Some(writer) = self.writer.take() {
+            if writer.join().is_err() {
+                error!("file store writer panicked");
+            }
+        }
+    }
+}
+
+impl MessageStore for FileStore {
+    fn append(&self, msg: &ServerMessage) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(msg)?;
+        line.push(b'\n');
+        let mut log = self.logs.entry(msg.topic.clone()).or_default();
+        log.last_sequence = msg.sequence;
+        log.lines += 1;
+        push_bounded(&mut log.messages, msg.clone(), self.capacity);
+        let path = self.path(&msg.topic, LOG_EXTENSION);
+        if log.lines <= 2 * self.capacity.max(1) {
+            return self.write(FileWrite::Append(path, line));
+        }
+        let (content, lines) = match log.messages.is_empty() {
+            // nothing kept, only the sequence
+            true => compacted([msg])?,
+            false => compacted(&log.messages)?,
+        };
+        log.lines = lines;
+        self.write(FileWrite::Replace(path, content))
+    }
+
+    fn range(&self, topic_id: &str, range: Range<u64>) -> anyhow::Result<Vec<ServerMessage>> {
+        Ok(match self.logs.get(topic_id) {
+            None => vec![],
+            Some(log) => log
+                .messages
+                .iter()
+                .filter(|msg| range.contains(&msg.sequence))
+                .cloned()
+                .collect(),
+        })
+    }
+
+    fn last_sequence(&self, topic_id: &str) -> anyhow::Result<u64> {
+        Ok(self
+            .logs
+            .get(topic_id)
+            .map(|log| log.last_sequence)
+            .unwrap_or_default())
+    }
+
+    fn list_topics(&self) -> anyhow::Result<Vec<String>> {
+        let mut topics: Vec<String> = self
+            .logs
+            .iter()
+            .map(|item| item.key().clone())
+            .chain(self.rooms.iter().map(|item| item.key().clone()))
+            .collect();
+        topics.sort();
+        topics.dedup();
+        Ok(topics)
+    }
+
+    fn delete_topic(&self, topic_id: &str) -> anyhow::Result<()> {
+        if self.logs.remove(topic_id).is_some() {
+            self.write(FileWrite::Remove(self.path(topic_id, LOG_EXTENSION)))?;
+        }
+        if self.rooms.remove(topic_id).is_some() {
+            self.write(FileWrite::Remove(self.path(topic_id, ROOM_EXTENSION)))?;
+        }
+        Ok(())
+    }
+
+    fn save_room(&self, topic_id: &str, room: &StoredRoom) -> anyhow::Result<()> {
+        let content = serde_json::to_vec(room)?;
+        self.rooms.insert(topic_id.to_string(), room.clone());
+        self.write(FileWrite::Replace(
+            self.path(topic_id, ROOM_EXTENSION),
+            content,
+        ))
+    }
+
+    fn load_room(&self, topic_id: &str) -> anyhow::Result<Option<StoredRoom>> {
+        Ok(self.rooms.get(topic_id).map(|room| room.clone()))
+    }
+}
+
+fn push_bounded(messages: &mut VecDeque<ServerMessage>, msg: ServerMessage, capacity: usize) {
+    if capacity == 0 {
+        return;
+    }
+    if messages.len() >= capacity {
+        messages.pop_front();
+    }
+    messages.push_back(msg);
+}
+
+// `messages` as a log, with its line count
+fn compacted<'a>(
+    messages: impl IntoIterator<Item = &'a ServerMessage>,
+) -> anyhow::Result<(Vec<u8>, usize)> {
+    let mut content = vec![];
+    let mut lines = 0;
+    for msg in messages {
+        serde_json::to_writer(&mut content, msg)?;
+        content.push(b'\n');
+        lines += 1;
+    }
+    Ok((content, lines))
+}
+
+// the writer thread, ends when the store is dropped
+fn write_files(writes: Receiver<FileWrite>, failed: &AtomicBool) {
+    // key: log path, value: opened for append
+    let mut files: HashMap<PathBuf, File> = HashMap::new();
+    for write in writes {
+        let result = match write {
+            FileWrite::Append(path, line) => append(&mut files, path, &line),
+            FileWrite::Replace(path, content) => {
+                files.remove(&path);
+                replace(&path, &content)
+            }
+            FileWrite::Remove(path) => {
+                files.remove(&path);
+                match fs::remove_file(&path) {
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                    result => result,
+                }
+            }
+        };
+        if let Err(e) = result {
+            error!("write topic file error: {e:?}");
+            failed.store(true, Ordering::Release);
+        }
+    }
+}
+
+fn append(files: &mut HashMap<PathBuf, File>, path: PathBuf, line: &[u8]) -> std::io::Result<()> {
+    let file = match files.entry(path) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(entry.key())?;
+            entry.insert(file)
+        }
+    };
+    file.write_all(line)
+}
+
+// a crash leaves either the old or the new file
+fn replace(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension(TMP_EXTENSION);
+    let mut file = File::create(&tmp)?;
+    file.write_all(content)?;
+    // on disk before it takes the place of the old one
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+fn read_log(path: &Path) -> anyhow::Result<Vec<ServerMessage>> {
+    let mut messages = vec![];
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.is_empty() {
+            messages.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(messages)
+}
+
+// topic id 可能包含任意字符，文件名使用 hex 编码
+fn encode_name(topic_id: &str) -> String {
+    let hex: String = topic_id.bytes().map(|b| format!("{b:02x}")).collect();
+    format!("{LOG_PREFIX}{hex}")
+}
+
+fn decode_name(name: &str) -> Option<String> {
+    let name = name.strip_prefix(LOG_PREFIX)?;
+    if !name.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..name.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(name.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_uid;
+    use crate::storage::{FileStore, MessageStore};
+    use crate::wire::{ChatMessage, ServerMessage};
+
+    fn message(topic_id: &str, sequence: u64) -> ServerMessage {
+        ServerMessage {
+            sequence,
+            topic: topic_id.into(),
+            event: Some(ChatMessage(format!("msg {sequence}"))),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn file_store_reopen() {
+        let dir = std::env::temp_dir().join(generate_uid());
+        let topic_id = "room/1";
+
+        let store = FileStore::open(&dir, 10).unwrap();
+        for sequence in 1..=3 {
+            store.append(&message(topic_id, sequence)).unwrap();
+        }
+        drop(store);
+
+        let store = FileStore::open(&dir, 10).unwrap();
+        assert_eq!(store.list_topics().unwrap(), vec![topic_id.to_string()]);
+        assert_eq!(store.last_sequence(topic_id).unwrap(), 3);
+        let messages = store.range(topic_id, 2..4).unwrap();
+        assert_eq!(
+            messages.iter().map(|m| m.sequence).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+
+        store.delete_topic(topic_id).unwrap();
+        assert!(store.list_topics().unwrap().is_empty());
+        drop(store);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_store_bounded() {
+        let dir = std::env::temp_dir().join(generate_uid());
+        let store = FileStore::open(&dir, 2).unwrap();
+        for sequence in 1..=10 {
+            store.append(&message("room", sequence)).unwrap();
+        }
+        let sequences = |store: &FileStore| {
+            let messages = store.range("room", 0..u64::MAX).unwrap();
+            messages.iter().map(|m| m.sequence).collect::<Vec<_>>()
+        };
+        assert_eq!(sequences(&store), vec![9, 10]);
+        drop(store);
+
+        // compacted, at most twice the capacity on disk
+        let log = std::fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+        let lines = std::fs::read_to_string(log.path()).unwrap().lines().count();
+        assert!(lines <= 4, "{lines} lines");
+        let store = FileStore::open(&dir, 2).unwrap();
+        assert_eq!(store.last_sequence("room").unwrap(), 10);
+        assert_eq!(sequences(&store), vec![9, 10]);
+        drop(store);
+
+        // nothing kept but the sequence
+        let store = FileStore::open(&dir, 0).unwrap();
+        store.append(&message("room", 11)).unwrap();
+        assert!(sequences(&store).is_empty());
+        drop(store);
+        let store = FileStore::open(&dir, 0).unwrap();
+        assert_eq!(store.last_sequence("room").unwrap(), 11);
+        drop(store);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_store_write_error() {
+        let dir = std::env::temp_dir().join(generate_uid());
+        let store = FileStore::open(&dir, 10).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        // written in the background, a later append reports the failure
+        let failed = (1..=100).any(|sequence| {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            store.append(&message("room", sequence)).is_err()
+        });
+        assert!(failed);
+    }
+}
@@ -0,0 +1,104 @@
+// 内存存储，重启后丢失
+
+use crate::storage::MessageStore;
+use crate::wire::ServerMessage;
+use dashmap::DashMap;
+use std::collections::VecDeque;
+use std::ops::Range;
+
+#[derive(Default)]
+struct TopicLog {
+    last_sequence: u64,
+    messages: VecDeque<ServerMessage>,
+}
+
+pub struct MemoryStore {
+    // max messages kept per topic, 0 keeps nothing
+    capacity: usize,
+    // key: topic_id, value: latest messages
+    logs: DashMap<String, TopicLog>,
+}
+
+impl MemoryStore {
+    pub fn new(capacity: usize) -> Self {
+        MemoryStore {
+            capacity,
+            logs: DashMap::new(),
+        }
+    }
+}
+
+impl MessageStore for MemoryStore {
+    fn append(&self, msg: &ServerMessage) -> anyhow::Result<()> {
+        let mut log = self.logs.entry(msg.topic.clone()).or_default();
+        log.last_sequence = msg.sequence;
+        if self.capacity > 0 {
+            if log.messages.len() >= self.capacity {
+                log.messages.pop_front();
+            }
+            log.messages.push_back(msg.clone());
+        }
+        Ok(())
+    }
+
+    fn range(&self, topic_id: &str, range: Range<u64>) -> anyhow::Result<Vec<ServerMessage>> {
+        Ok(match self.logs.get(topic_id) {
+            None => vec![],
+            Some(log) => log
+                .messages
+                .iter()
+                .filter(|msg| range.contains(&msg.sequence))
+                .cloned()
+                .collect(),
+        })
+    }
+
+    fn last_sequence(&self, topic_id: &str) -> anyhow::Result<u64> {
+        Ok(self
+            .logs
+            .get(topic_id)
+            .map(|log| log.last_sequence)
+            .unwrap_or_default())
+    }
+
+    fn list_topics(&self) -> anyhow::Result<Vec<String>> {
+        Ok(self.logs.iter().map(|item| item.key().clone()).collect())
+    }
+
+    fn delete_topic(&self, topic_id: &str) -> anyhow::Result<()> {
+        self.logs.remove(topic_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::{MemoryStore, MessageStore};
+    use crate::wire::{ChatMessage, ServerMessage};
+
+    #[test]
+    fn memory_store_bounded() {
+        let store = MemoryStore::new(2);
+        for sequence in 1..=3 {
+            store
+                .append(&ServerMessage {
+                    sequence,
+                    topic: "room".into(),
+                    event: Some(ChatMessage(format!("msg {sequence}"))),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        let messages = store.range("room", 0..u64::MAX).unwrap();
+        assert_eq!(
+            messages.iter().map(|m| m.sequence).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(store.last_sequence("room").unwrap(), 3);
+        assert_eq!(store.list_topics().unwrap(), vec!["room".to_string()]);
+
+        store.delete_topic("room").unwrap();
+        assert_eq!(store.last_sequence("room").unwrap(), 0);
+    }
+}
@@ -0,0 +1,7 @@
+mod file;
+mod memory;
+mod store;
+
+pub use self::file::*;
+pub use self::memory::*;
+pub use self::store::*;
@@ -0,0 +1,37 @@
+// topic 消息存储
+
+use crate::wire::ServerMessage;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+// room settings stored next to its messages
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StoredRoom {
+    pub owner: String,
+    pub persistent: bool,
+}
+
+pub trait MessageStore: Send + Sync {
+    // append a published message, sequences of a topic are increasing
+    fn append(&self, msg: &ServerMessage) -> anyhow::Result<()>;
+
+    // messages of `topic_id` with a sequence inside `range`, oldest first
+    fn range(&self, topic_id: &str, range: Range<u64>) -> anyhow::Result<Vec<ServerMessage>>;
+
+    // last sequence appended to `topic_id`, 0 if none
+    fn last_sequence(&self, topic_id: &str) -> anyhow::Result<u64>;
+
+    // topics with messages or a saved room
+    fn list_topics(&self) -> anyhow::Result<Vec<String>>;
+
+    fn save_room(&self, _topic_id: &str, _room: &StoredRoom) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    // None for stores without rooms and for topics stored before rooms were
+    fn load_room(&self, _topic_id: &str) -> anyhow::Result<Option<StoredRoom>> {
+        Ok(None)
+    }
+
+    fn delete_topic(&self, topic_id: &str) -> anyhow::Result<()>;
+}
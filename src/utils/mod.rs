@@ -1,3 +1,5 @@
+mod time;
 mod uid;
 
+pub use self::time::*;
 pub use self::uid::*;
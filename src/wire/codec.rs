@@ -0,0 +1,91 @@
+// 消息编码，由传输层协商
+
+use crate::wire::{ClientMessage, InvalidFrame};
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Json,
+    // prost, smaller and faster than json
+    Protobuf,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::Protobuf => "protobuf",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name {
+            "json" => Some(Encoding::Json),
+            "protobuf" => Some(Encoding::Protobuf),
+            _ => None,
+        }
+    }
+
+    pub fn encode<M: prost::Message + Serialize>(&self, msg: &M) -> anyhow::Result<Bytes> {
+        Ok(match self {
+            Encoding::Json => Bytes::from(serde_json::to_vec(msg)?),
+            Encoding::Protobuf => Bytes::from(msg.encode_to_vec()),
+        })
+    }
+
+    pub fn decode<M: prost::Message + Default + DeserializeOwned>(
+        &self,
+        buf: &[u8],
+    ) -> anyhow::Result<M> {
+        Ok(match self {
+            Encoding::Json => serde_json::from_slice(buf)?,
+            Encoding::Protobuf => M::decode(buf)?,
+        })
+    }
+
+    // a frame of a client, errors are InvalidFrame
+    pub fn decode_client(&self, buf: &[u8]) -> anyhow::Result<ClientMessage> {
+        match self {
+            Encoding::Json => serde_json::from_slice(buf).map_err(|e| InvalidFrame::json(buf, e)),
+            Encoding::Protobuf => prost::Message::decode(buf).map_err(InvalidFrame::protobuf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wire::{ClientMessage, Encoding, InvalidFrame, SendMessage};
+
+    #[test]
+    fn encoding_round_trip() {
+        let msg = ClientMessage {
+            topic: "room".into(),
+            message: Some(SendMessage("hi".into())),
+            request_id: "1".into(),
+            ..Default::default()
+        };
+        for encoding in [Encoding::Json, Encoding::Protobuf] {
+            let buf = encoding.encode(&msg).unwrap();
+            assert_eq!(encoding.decode::<ClientMessage>(&buf).unwrap(), msg);
+            assert_eq!(Encoding::from_name(encoding.name()), Some(encoding));
+        }
+        assert!(Encoding::Protobuf.decode::<ClientMessage>(b"{}").is_err());
+    }
+
+    #[test]
+    fn decode_client() {
+        let err = Encoding::Json
+            .decode_client(br#"{"request_id":"7","message":1}"#)
+            .unwrap_err();
+        assert_eq!(err.downcast::<InvalidFrame>().unwrap().request_id, "7");
+        let err = Encoding::Protobuf.decode_client(b"{}").unwrap_err();
+        assert!(err.downcast::<InvalidFrame>().is_ok());
+
+        // join_user of old clients, its field is gone
+        let msg = Encoding::Protobuf.decode_client(&[0x22, 0]).unwrap();
+        assert_eq!(msg.message, None);
+    }
+}
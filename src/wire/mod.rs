@@ -1,15 +1,56 @@
+mod codec;
+// generated by build.rs, kept as prost writes it
+#[allow(clippy::module_inception)]
+#[rustfmt::skip]
 mod wire;
 
-pub use self::wire::{client_message::Message::SendMessage, *};
+pub use self::codec::*;
+pub use self::wire::{
+    client_message::Message::SendMessage, server_message::Event,
+    server_message::Event::ChatMessage, *,
+};
+use crate::utils::timestamp_millis;
 use bytes::Bytes;
 
 // 协议
 
+// a client frame that does not decode, it is answered with an error and the
+// connection stays open
+#[derive(Debug, thiserror::Error)]
+#[error("{reason}")]
+pub struct InvalidFrame {
+    // empty unless the frame is a json object with a request_id
+    pub request_id: String,
+    pub reason: String,
+}
+
+impl InvalidFrame {
+    pub(crate) fn protobuf(err: prost::DecodeError) -> anyhow::Error {
+        InvalidFrame {
+            request_id: String::new(),
+            reason: err.to_string(),
+        }
+        .into()
+    }
+
+    pub(crate) fn json(buf: &[u8], err: serde_json::Error) -> anyhow::Error {
+        let request_id = serde_json::from_slice::<serde_json::Value>(buf)
+            .ok()
+            .and_then(|value| Some(value.get("request_id")?.as_str()?.to_string()))
+            .unwrap_or_default();
+        InvalidFrame {
+            request_id,
+            reason: err.to_string(),
+        }
+        .into()
+    }
+}
+
 impl TryFrom<String> for ClientMessage {
     type Error = anyhow::Error;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        Ok(serde_json::from_str(&value)?)
+        serde_json::from_str(&value).map_err(|e| InvalidFrame::json(value.as_bytes(), e))
     }
 }
 
@@ -25,7 +66,7 @@ impl TryFrom<Bytes> for ClientMessage {
     type Error = anyhow::Error;
 
     fn try_from(value: Bytes) -> Result<Self, Self::Error> {
-        Ok(serde_json::from_slice(&value)?)
+        serde_json::from_slice(&value).map_err(|e| InvalidFrame::json(&value, e))
     }
 }
 
@@ -62,10 +103,29 @@ impl ClientMessage {
     }
 }
 
+impl ServerMessage {
+    // event outside the topic history, sequence stays 0
+    pub fn event(topic: &str, event: Event) -> Self {
+        ServerMessage {
+            topic: topic.to_string(),
+            event: Some(event),
+            timestamp: timestamp_millis(),
+            ..Default::default()
+        }
+    }
+
+    pub fn get_message_string(&self) -> Option<String> {
+        if let Some(ChatMessage(msg)) = &self.event {
+            return Some(msg.clone());
+        }
+        None
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::wire::client_message::Message;
-    use crate::wire::{ClientMessage, JoinRoom, Login};
+    use crate::wire::{ClientMessage, Error, ErrorCode, Event, JoinRoom, Login, ServerMessage};
 
     impl TryFrom<ClientMessage> for String {
         type Error = anyhow::Error;
@@ -82,6 +142,7 @@ mod test {
         let message = ClientMessage {
             topic: "a".into(),
             message: Some(Message::SendMessage("hello world".into())),
+            ..Default::default()
         };
 
         let x: String = message.try_into().unwrap();
@@ -94,7 +155,9 @@ mod test {
             topic: "".into(),
             message: Some(Message::Login(Login {
                 name: "hello world".into(),
+                ..Default::default()
             })),
+            ..Default::default()
         };
 
         let x: String = message.try_into().unwrap();
@@ -106,10 +169,54 @@ mod test {
         let message = ClientMessage {
             topic: "room1".into(),
             message: Some(Message::JoinRoom(JoinRoom {})),
+            ..Default::default()
         };
 
         let x: String = message.try_into().unwrap();
         assert_eq!(r#"{"topic":"room1","message":{"join_room":{}}}"#, &x);
+
+        let message = ClientMessage {
+            topic: "room1".into(),
+            message: Some(Message::JoinRoom(JoinRoom {})),
+            request_id: "1".into(),
+            ..Default::default()
+        };
+
+        let x: String = message.try_into().unwrap();
+        assert_eq!(
+            r#"{"topic":"room1","request_id":"1","message":{"join_room":{}}}"#,
+            &x
+        );
+    }
+
+    #[test]
+    fn encode_event() {
+        let message = ServerMessage {
+            sequence: 1,
+            topic: "a".into(),
+            event: Some(Event::ChatMessage("hello world".into())),
+            user_name: "bob".into(),
+            ..Default::default()
+        };
+        let x: String = message.try_into().unwrap();
+        assert_eq!(
+            r#"{"sequence":1,"topic":"a","user_name":"bob","session_id":"","timestamp":0,"event":{"chat_message":"hello world"}}"#,
+            &x
+        );
+
+        let message = ServerMessage {
+            topic: "a".into(),
+            event: Some(Event::Error(Error {
+                code: ErrorCode::TopicNotFound as i32,
+                message: "topic not found: a".into(),
+            })),
+            ..Default::default()
+        };
+        let x: String = message.try_into().unwrap();
+        assert_eq!(
+            r#"{"sequence":0,"topic":"a","user_name":"","session_id":"","timestamp":0,"event":{"error":{"code":1,"message":"topic not found: a"}}}"#,
+            &x
+        );
     }
 
     #[test]
@@ -120,8 +227,24 @@ mod test {
             ClientMessage {
                 topic: "a".into(),
                 message: Some(Message::SendMessage("hello world".into())),
+                ..Default::default()
             },
             result
         );
     }
+
+    #[test]
+    fn decode_omitted_fields() {
+        for data in [
+            r#"{"topic":"a","message":{"create_room":{}}}"#,
+            r#"{"topic":"a","message":{"typing":{}}}"#,
+            r#"{"topic":"a","message":{"fetch_history":{}}}"#,
+            r#"{"topic":"","message":{"resume":{"session_token":"t"}}}"#,
+            r#"{"topic":"","message":{"subscribe_presence":{}}}"#,
+            r#"{"topic":"","message":{"set_presence":{}}}"#,
+        ] {
+            let result: anyhow::Result<ClientMessage> = data.to_string().try_into();
+            assert!(result.is_ok(), "{data}: {result:?}");
+        }
+    }
 }
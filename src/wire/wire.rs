@@ -2,10 +2,18 @@
 #[serde(rename_all = "snake_case")]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ClientMessage {
-    /// 消息路由的主题，可以是p2p或room
+    /// 消息路由的 room，私聊用 send_direct
     #[prost(string, tag="1")]
     pub topic: ::prost::alloc::string::String,
-    #[prost(oneof="client_message::Message", tags="2, 3, 4, 5, 6, 7, 8")]
+    /// 客户端生成，服务端的 Ack/Error 会带回同一个 id
+    #[prost(string, tag="10")]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub request_id: ::prost::alloc::string::String,
+    /// 客户端生成的 send_message 幂等键，重试时不变，重复的消息只 ack 原来的 sequence
+    #[prost(string, tag="19")]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub message_id: ::prost::alloc::string::String,
+    #[prost(oneof="client_message::Message", tags="2, 3, 6, 7, 8, 9, 11, 12, 13, 14, 15, 16, 17, 18")]
     pub message: ::core::option::Option<client_message::Message>,
 }
 /// Nested message and enum types in `ClientMessage`.
@@ -18,10 +26,6 @@ pub mod client_message {
         JoinRoom(super::JoinRoom),
         #[prost(message, tag="3")]
         LeaveRoom(super::LeaveRoom),
-        #[prost(message, tag="4")]
-        JoinUser(super::JoinUser),
-        #[prost(message, tag="5")]
-        LeaveUser(super::LeaveUser),
         #[prost(string, tag="6")]
         #[serde(rename = "send_message")]
         SendMessage(::prost::alloc::string::String),
@@ -29,6 +33,24 @@ pub mod client_message {
         CreateRoom(super::CreateRoom),
         #[prost(message, tag="8")]
         Login(super::Login),
+        #[prost(message, tag="9")]
+        FetchHistory(super::FetchHistory),
+        #[prost(message, tag="11")]
+        SendDirect(super::SendDirect),
+        #[prost(message, tag="12")]
+        DeleteRoom(super::DeleteRoom),
+        #[prost(message, tag="13")]
+        ListRooms(super::ListRooms),
+        #[prost(message, tag="14")]
+        ListMembers(super::ListMembers),
+        #[prost(message, tag="15")]
+        SubscribePresence(super::SubscribePresence),
+        #[prost(message, tag="16")]
+        SetPresence(super::SetPresence),
+        #[prost(message, tag="17")]
+        Typing(super::Typing),
+        #[prost(message, tag="18")]
+        Resume(super::Resume),
     }
 }
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -41,38 +63,431 @@ pub struct JoinRoom {
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct LeaveRoom {
 }
+/// fails if the room exists, the creator becomes its owner and joins it
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[derive(Clone, PartialEq, ::prost::Message)]
-pub struct JoinUser {
+pub struct CreateRoom {
+    /// keep the room and its history when the last member leaves
+    #[prost(bool, tag="1")]
+    #[serde(default)]
+    pub persistent: bool,
 }
+/// owner only, members receive room_deleted
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[derive(Clone, PartialEq, ::prost::Message)]
-pub struct LeaveUser {
+pub struct DeleteRoom {
 }
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[derive(Clone, PartialEq, ::prost::Message)]
-pub struct CreateRoom {
+pub struct Login {
+    #[prost(string, tag="1")]
+    pub name: ::prost::alloc::string::String,
+    /// checked by the server Authenticator, unused by the default one
+    #[prost(string, tag="2")]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub password: ::prost::alloc::string::String,
+    #[prost(string, tag="3")]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub token: ::prost::alloc::string::String,
 }
+/// 私聊，投递给 to_user 的所有在线 session，并回显给发送者的其他 session
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[derive(Clone, PartialEq, ::prost::Message)]
-pub struct Login {
+pub struct SendDirect {
     #[prost(string, tag="1")]
-    pub name: ::prost::alloc::string::String,
+    pub to_user: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub text: ::prost::alloc::string::String,
+}
+/// all rooms, answered by Ack.room_list
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListRooms {
+}
+/// members of the topic, answered by Ack.member_list
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListMembers {
+}
+/// contact list, replaces the previous one, current presence of each user is sent right away
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubscribePresence {
+    #[prost(string, repeated, tag="1")]
+    #[serde(default)]
+    pub users: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// e.g. idle when the client is in the background
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SetPresence {
+    #[prost(enumeration="PresenceStatus", tag="1")]
+    #[serde(default)]
+    pub status: i32,
+}
+/// typing indicator, sent again while typing, the server stops it after a few seconds without one
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Typing {
+    #[prost(bool, tag="1")]
+    #[serde(default)]
+    pub active: bool,
+}
+/// instead of login on a new connection, continues the session of Ack.session_token
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Resume {
+    #[prost(string, tag="1")]
+    pub session_token: ::prost::alloc::string::String,
+    /// last sequence received per topic, later messages are replayed
+    #[prost(map="string, uint64", tag="2")]
+    #[serde(default)]
+    pub last_seen: ::std::collections::HashMap<::prost::alloc::string::String, u64>,
+}
+/// 拉取历史消息
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FetchHistory {
+    /// only messages with a smaller sequence, 0 means latest
+    #[prost(uint64, tag="1")]
+    #[serde(default)]
+    pub before_sequence: u64,
+    /// max messages returned, 0 means all kept
+    #[prost(uint32, tag="2")]
+    #[serde(default)]
+    pub limit: u32,
 }
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ServerMessage {
+    /// 0 for events which are not part of the topic history
+    #[prost(uint64, tag="1")]
+    pub sequence: u64,
+    #[prost(string, tag="2")]
+    pub topic: ::prost::alloc::string::String,
+    /// 发送者，来自 Login
+    #[prost(string, tag="4")]
+    pub user_name: ::prost::alloc::string::String,
+    #[prost(string, tag="5")]
+    pub session_id: ::prost::alloc::string::String,
+    /// server time in unix milliseconds
+    #[prost(uint64, tag="6")]
+    pub timestamp: u64,
+    /// request_id of the command this Ack/Error answers
+    #[prost(string, tag="12")]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub request_id: ::prost::alloc::string::String,
+    /// message_id given by the sender of a chat_message
+    #[prost(string, tag="18")]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub message_id: ::prost::alloc::string::String,
+    #[prost(oneof="server_message::Event", tags="3, 7, 8, 9, 10, 11, 13, 14, 15, 16, 17, 19")]
+    pub event: ::core::option::Option<server_message::Event>,
+}
+/// Nested message and enum types in `ServerMessage`.
+pub mod server_message {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Event {
+        #[prost(string, tag="3")]
+        ChatMessage(::prost::alloc::string::String),
+        #[prost(message, tag="7")]
+        MemberJoined(super::MemberJoined),
+        #[prost(message, tag="8")]
+        MemberLeft(super::MemberLeft),
+        #[prost(message, tag="9")]
+        Ack(super::Ack),
+        #[prost(message, tag="10")]
+        Error(super::Error),
+        #[prost(message, tag="11")]
+        Presence(super::Presence),
+        #[prost(message, tag="13")]
+        Kicked(super::Kicked),
+        #[prost(message, tag="14")]
+        DirectMessage(super::DirectMessage),
+        #[prost(message, tag="15")]
+        RoomDeleted(super::RoomDeleted),
+        /// user_name started or stopped typing, not part of the topic history
+        #[prost(message, tag="16")]
+        Typing(super::Typing),
+        #[prost(message, tag="17")]
+        Gap(super::Gap),
+        #[prost(message, tag="19")]
+        GoingAway(super::GoingAway),
+    }
+}
+/// user_name joined the topic
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MemberJoined {
+}
+/// user_name left the topic
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MemberLeft {
+}
+/// the topic was deleted by user_name, no more events follow
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RoomDeleted {
+}
+/// this session was too slow and missed these messages of the topic, fetch_history can fill them
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Gap {
+    #[prost(uint64, tag="1")]
+    pub from_sequence: u64,
+    #[prost(uint64, tag="2")]
+    pub to_sequence: u64,
+}
+/// command accepted
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Ack {
+    /// sequence assigned to a send_message
     #[prost(uint64, tag="1")]
     pub sequence: u64,
+    /// signed token issued on login, usable to authenticate later connections
     #[prost(string, tag="2")]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub token: ::prost::alloc::string::String,
+    /// answer of list_rooms
+    #[prost(message, optional, tag="3")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room_list: ::core::option::Option<RoomList>,
+    /// answer of list_members
+    #[prost(message, optional, tag="4")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub member_list: ::core::option::Option<MemberList>,
+    /// issued on login and resume, resumes this session on a new connection within the grace period
+    #[prost(string, tag="5")]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub session_token: ::prost::alloc::string::String,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RoomInfo {
+    #[prost(string, tag="1")]
+    pub topic: ::prost::alloc::string::String,
+    /// distinct users subscribed
+    #[prost(uint32, tag="2")]
+    pub members: u32,
+    #[prost(string, tag="3")]
+    pub owner: ::prost::alloc::string::String,
+    #[prost(bool, tag="4")]
+    pub persistent: bool,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RoomList {
+    #[prost(message, repeated, tag="1")]
+    pub rooms: ::prost::alloc::vec::Vec<RoomInfo>,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MemberList {
+    #[prost(string, repeated, tag="1")]
+    pub members: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Error {
+    #[prost(enumeration="ErrorCode", tag="1")]
+    pub code: i32,
+    #[prost(string, tag="2")]
+    pub message: ::prost::alloc::string::String,
+}
+/// direct message from user_name, not part of any topic
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DirectMessage {
+    #[prost(string, tag="1")]
+    pub to_user: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub text: ::prost::alloc::string::String,
+}
+/// the session is closed by the server after this event
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Kicked {
+    #[prost(string, tag="1")]
+    pub reason: ::prost::alloc::string::String,
+}
+/// the server is shutting down, the session is closed once pending messages are sent
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GoingAway {
+    #[prost(string, tag="1")]
+    pub reason: ::prost::alloc::string::String,
+}
+/// presence of user_name, sent to its rooms and to sessions subscribed to the user
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Presence {
+    #[prost(enumeration="PresenceStatus", tag="1")]
+    pub status: i32,
+    /// unix millis of the last status change
+    #[prost(uint64, tag="2")]
+    pub last_seen: u64,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListTopicsRequest {
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetTopicRequest {
+    #[prost(string, tag="1")]
+    pub topic: ::prost::alloc::string::String,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TopicDetail {
+    #[prost(message, optional, tag="1")]
+    pub room: ::core::option::Option<RoomInfo>,
+    /// ordered user names
+    #[prost(string, repeated, tag="2")]
+    pub members: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListSessionsRequest {
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SessionInfo {
+    #[prost(string, tag="1")]
+    pub id: ::prost::alloc::string::String,
+    /// empty before login
+    #[prost(string, tag="2")]
+    pub user_name: ::prost::alloc::string::String,
+    /// subscribed topics, ordered
+    #[prost(string, repeated, tag="3")]
+    pub topics: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SessionList {
+    #[prost(message, repeated, tag="1")]
+    pub sessions: ::prost::alloc::vec::Vec<SessionInfo>,
+}
+/// closes the session for good, it can not be resumed
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct KickSessionRequest {
+    #[prost(string, tag="1")]
+    pub session_id: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub reason: ::prost::alloc::string::String,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct KickSessionResponse {
+}
+/// chat message published by the server, e.g. for an integration
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PublishRequest {
+    #[prost(string, tag="1")]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub topic: ::prost::alloc::string::String,
+    /// shown as the sender
+    #[prost(string, tag="2")]
+    pub user_name: ::prost::alloc::string::String,
+    #[prost(string, tag="3")]
+    pub text: ::prost::alloc::string::String,
+    /// retries with the same id are published once
+    #[prost(string, tag="4")]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub message_id: ::prost::alloc::string::String,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PublishResponse {
+    #[prost(uint64, tag="1")]
+    pub sequence: u64,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetHistoryRequest {
+    #[prost(string, tag="1")]
     pub topic: ::prost::alloc::string::String,
-    #[prost(string, optional, tag="3")]
-    pub message: ::core::option::Option<::prost::alloc::string::String>,
+    /// only messages with a smaller sequence, 0 means latest
+    #[prost(uint64, tag="2")]
+    pub before_sequence: u64,
+    /// max messages returned, 0 means all kept
+    #[prost(uint32, tag="3")]
+    pub limit: u32,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct History {
+    #[prost(message, repeated, tag="1")]
+    pub messages: ::prost::alloc::vec::Vec<ServerMessage>,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum ErrorCode {
+    Unknown = 0,
+    TopicNotFound = 1,
+    NotSubscribed = 2,
+    Internal = 3,
+    /// the frame does not decode or carries no known command
+    InvalidMessage = 4,
+    Unauthenticated = 5,
+    AlreadyLoggedIn = 6,
+    UserOffline = 7,
+    TopicExists = 8,
+    PermissionDenied = 9,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum PresenceStatus {
+    Offline = 0,
+    Online = 1,
+    Idle = 2,
 }
 /// Generated client implementations.
 pub mod chat_service_client {
@@ -142,9 +557,186 @@ pub mod chat_service_client {
             &mut self,
             request: impl tonic::IntoStreamingRequest<Message = super::ClientMessage>,
         ) -> Result<
-                tonic::Response<tonic::codec::Streaming<super::ServerMessage>>,
-                tonic::Status,
-            > {
+            tonic::Response<tonic::codec::Streaming<super::ServerMessage>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/wire.ChatService/SendMessage",
+            );
+            self.inner.streaming(request.into_streaming_request(), path, codec).await
+        }
+    }
+}
+/// Generated client implementations.
+pub mod chat_admin_client {
+    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+    use tonic::codegen::*;
+    /// unary calls for ops tooling, no chat stream needed
+    #[derive(Debug, Clone)]
+    pub struct ChatAdminClient<T> {
+        inner: tonic::client::Grpc<T>,
+    }
+    impl ChatAdminClient<tonic::transport::Channel> {
+        /// Attempt to create a new client by connecting to a given endpoint.
+        pub async fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
+        where
+            D: std::convert::TryInto<tonic::transport::Endpoint>,
+            D::Error: Into<StdError>,
+        {
+            let conn = tonic::transport::Endpoint::new(dst)?.connect().await?;
+            Ok(Self::new(conn))
+        }
+    }
+    impl<T> ChatAdminClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::BoxBody>,
+        T::Error: Into<StdError>,
+        T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+        <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> ChatAdminClient<InterceptedService<T, F>>
+        where
+            F: tonic::service::Interceptor,
+            T::ResponseBody: Default,
+            T: tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+                Response = http::Response<
+                    <T as tonic::client::GrpcService<tonic::body::BoxBody>>::ResponseBody,
+                >,
+            >,
+            <T as tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+            >>::Error: Into<StdError> + Send + Sync,
+        {
+            ChatAdminClient::new(InterceptedService::new(inner, interceptor))
+        }
+        /// Compress requests with `gzip`.
+        ///
+        /// This requires the server to support it otherwise it might respond with an
+        /// error.
+        #[must_use]
+        pub fn send_gzip(mut self) -> Self {
+            self.inner = self.inner.send_gzip();
+            self
+        }
+        /// Enable decompressing responses with `gzip`.
+        #[must_use]
+        pub fn accept_gzip(mut self) -> Self {
+            self.inner = self.inner.accept_gzip();
+            self
+        }
+        pub async fn list_topics(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListTopicsRequest>,
+        ) -> Result<tonic::Response<super::RoomList>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/wire.ChatAdmin/ListTopics",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn get_topic(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetTopicRequest>,
+        ) -> Result<tonic::Response<super::TopicDetail>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/wire.ChatAdmin/GetTopic");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn list_sessions(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListSessionsRequest>,
+        ) -> Result<tonic::Response<super::SessionList>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/wire.ChatAdmin/ListSessions",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn kick_session(
+            &mut self,
+            request: impl tonic::IntoRequest<super::KickSessionRequest>,
+        ) -> Result<tonic::Response<super::KickSessionResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/wire.ChatAdmin/KickSession",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn publish(
+            &mut self,
+            request: impl tonic::IntoRequest<super::PublishRequest>,
+        ) -> Result<tonic::Response<super::PublishResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/wire.ChatAdmin/Publish");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn get_history(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetHistoryRequest>,
+        ) -> Result<tonic::Response<super::History>, tonic::Status> {
             self.inner
                 .ready()
                 .await
@@ -156,9 +748,9 @@ pub mod chat_service_client {
                 })?;
             let codec = tonic::codec::ProstCodec::default();
             let path = http::uri::PathAndQuery::from_static(
-                "/wire.ChatService/SendMessage",
+                "/wire.ChatAdmin/GetHistory",
             );
-            self.inner.streaming(request.into_streaming_request(), path, codec).await
+            self.inner.unary(request.into_request(), path, codec).await
         }
     }
 }
@@ -309,3 +901,352 @@ pub mod chat_service_server {
         const NAME: &'static str = "wire.ChatService";
     }
 }
+/// Generated server implementations.
+pub mod chat_admin_server {
+    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+    use tonic::codegen::*;
+    ///Generated trait containing gRPC methods that should be implemented for use with ChatAdminServer.
+    #[async_trait]
+    pub trait ChatAdmin: Send + Sync + 'static {
+        async fn list_topics(
+            &self,
+            request: tonic::Request<super::ListTopicsRequest>,
+        ) -> Result<tonic::Response<super::RoomList>, tonic::Status>;
+        async fn get_topic(
+            &self,
+            request: tonic::Request<super::GetTopicRequest>,
+        ) -> Result<tonic::Response<super::TopicDetail>, tonic::Status>;
+        async fn list_sessions(
+            &self,
+            request: tonic::Request<super::ListSessionsRequest>,
+        ) -> Result<tonic::Response<super::SessionList>, tonic::Status>;
+        async fn kick_session(
+            &self,
+            request: tonic::Request<super::KickSessionRequest>,
+        ) -> Result<tonic::Response<super::KickSessionResponse>, tonic::Status>;
+        async fn publish(
+            &self,
+            request: tonic::Request<super::PublishRequest>,
+        ) -> Result<tonic::Response<super::PublishResponse>, tonic::Status>;
+        async fn get_history(
+            &self,
+            request: tonic::Request<super::GetHistoryRequest>,
+        ) -> Result<tonic::Response<super::History>, tonic::Status>;
+    }
+    /// unary calls for ops tooling, no chat stream needed
+    #[derive(Debug)]
+    pub struct ChatAdminServer<T: ChatAdmin> {
+        inner: _Inner<T>,
+        accept_compression_encodings: (),
+        send_compression_encodings: (),
+    }
+    struct _Inner<T>(Arc<T>);
+    impl<T: ChatAdmin> ChatAdminServer<T> {
+        pub fn new(inner: T) -> Self {
+            Self::from_arc(Arc::new(inner))
+        }
+        pub fn from_arc(inner: Arc<T>) -> Self {
+            let inner = _Inner(inner);
+            Self {
+                inner,
+                accept_compression_encodings: Default::default(),
+                send_compression_encodings: Default::default(),
+            }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> InterceptedService<Self, F>
+        where
+            F: tonic::service::Interceptor,
+        {
+            InterceptedService::new(Self::new(inner), interceptor)
+        }
+    }
+    impl<T, B> tonic::codegen::Service<http::Request<B>> for ChatAdminServer<T>
+    where
+        T: ChatAdmin,
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+        fn poll_ready(
+            &mut self,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            let inner = self.inner.clone();
+            match req.uri().path() {
+                "/wire.ChatAdmin/ListTopics" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListTopicsSvc<T: ChatAdmin>(pub Arc<T>);
+                    impl<
+                        T: ChatAdmin,
+                    > tonic::server::UnaryService<super::ListTopicsRequest>
+                    for ListTopicsSvc<T> {
+                        type Response = super::RoomList;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListTopicsRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).list_topics(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ListTopicsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/wire.ChatAdmin/GetTopic" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetTopicSvc<T: ChatAdmin>(pub Arc<T>);
+                    impl<
+                        T: ChatAdmin,
+                    > tonic::server::UnaryService<super::GetTopicRequest>
+                    for GetTopicSvc<T> {
+                        type Response = super::TopicDetail;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetTopicRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).get_topic(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = GetTopicSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/wire.ChatAdmin/ListSessions" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListSessionsSvc<T: ChatAdmin>(pub Arc<T>);
+                    impl<
+                        T: ChatAdmin,
+                    > tonic::server::UnaryService<super::ListSessionsRequest>
+                    for ListSessionsSvc<T> {
+                        type Response = super::SessionList;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListSessionsRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).list_sessions(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ListSessionsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/wire.ChatAdmin/KickSession" => {
+                    #[allow(non_camel_case_types)]
+                    struct KickSessionSvc<T: ChatAdmin>(pub Arc<T>);
+                    impl<
+                        T: ChatAdmin,
+                    > tonic::server::UnaryService<super::KickSessionRequest>
+                    for KickSessionSvc<T> {
+                        type Response = super::KickSessionResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::KickSessionRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).kick_session(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = KickSessionSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/wire.ChatAdmin/Publish" => {
+                    #[allow(non_camel_case_types)]
+                    struct PublishSvc<T: ChatAdmin>(pub Arc<T>);
+                    impl<T: ChatAdmin> tonic::server::UnaryService<super::PublishRequest>
+                    for PublishSvc<T> {
+                        type Response = super::PublishResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::PublishRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).publish(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = PublishSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/wire.ChatAdmin/GetHistory" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetHistorySvc<T: ChatAdmin>(pub Arc<T>);
+                    impl<
+                        T: ChatAdmin,
+                    > tonic::server::UnaryService<super::GetHistoryRequest>
+                    for GetHistorySvc<T> {
+                        type Response = super::History;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetHistoryRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).get_history(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = GetHistorySvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                _ => {
+                    Box::pin(async move {
+                        Ok(
+                            http::Response::builder()
+                                .status(200)
+                                .header("grpc-status", "12")
+                                .header("content-type", "application/grpc")
+                                .body(empty_body())
+                                .unwrap(),
+                        )
+                    })
+                }
+            }
+        }
+    }
+    impl<T: ChatAdmin> Clone for ChatAdminServer<T> {
+        fn clone(&self) -> Self {
+            let inner = self.inner.clone();
+            Self {
+                inner,
+                accept_compression_encodings: self.accept_compression_encodings,
+                send_compression_encodings: self.send_compression_encodings,
+            }
+        }
+    }
+    impl<T: ChatAdmin> Clone for _Inner<T> {
+        fn clone(&self) -> Self {
+            Self(self.0.clone())
+        }
+    }
+    impl<T: std::fmt::Debug> std::fmt::Debug for _Inner<T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{:?}", self.0)
+        }
+    }
+    impl<T: ChatAdmin> tonic::transport::NamedService for ChatAdminServer<T> {
+        const NAME: &'static str = "wire.ChatAdmin";
+    }
+}